@@ -0,0 +1,119 @@
+use std::io::{Read, Write};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+
+use crate::cli::Bzip2Cli;
+use crate::options::CompressionOptions;
+use crate::{bzip2, Error, Result};
+
+/// The size of the chunks passed between the async side and the blocking worker.
+const CHUNK_SIZE: usize = 64 * 1024;
+/// The number of chunks buffered in each direction.
+const CHANNEL_DEPTH: usize = 4;
+
+/// Compresses `reader` into `writer` without blocking the async runtime.
+/// Returns the number of bytes written to `writer`.
+pub async fn compress_async<R, W>(reader: R, writer: W, options: impl Into<CompressionOptions>) -> Result<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let options = options.into();
+    run_blocking(reader, writer, move |input, output| bzip2::compress(input, output, &options).map(|_| ())).await
+}
+
+/// Decompresses `reader` into `writer` without blocking the async runtime.
+/// Returns the number of bytes written to `writer`.
+pub async fn decompress_async<R, W>(reader: R, writer: W) -> Result<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    run_blocking(reader, writer, |input, output| bzip2::decompress(input, output, &Bzip2Cli::default()).map(|_| ())).await
+}
+
+/// Runs the CPU-bound `operation` on the blocking thread pool, streaming the input from `reader`
+/// to it and its output to `writer` through channels.
+async fn run_blocking<R, W, F>(mut reader: R, mut writer: W, operation: F) -> Result<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+    F: FnOnce(ChannelReader, ChannelWriter) -> Result<()> + Send + 'static,
+{
+    let (input_tx, input_rx) = channel::<Vec<u8>>(CHANNEL_DEPTH);
+    let (output_tx, mut output_rx) = channel::<Vec<u8>>(CHANNEL_DEPTH);
+    let task = tokio::task::spawn_blocking(move || {
+        operation(ChannelReader::new(input_rx), ChannelWriter(output_tx))
+    });
+    // both halves own their channel end, so that a failure on one side unblocks the worker.
+    let feed = async move {
+        let mut buffer = vec![0u8; CHUNK_SIZE];
+        loop {
+            let len = reader.read(&mut buffer).await?;
+            if len == 0 || input_tx.send(buffer[..len].to_vec()).await.is_err() {
+                break Ok::<(), std::io::Error>(());
+            }
+        }
+    };
+    let drain = async move {
+        let mut written = 0u64;
+        while let Some(chunk) = output_rx.recv().await {
+            writer.write_all(&chunk).await?;
+            written += chunk.len() as u64;
+        }
+        writer.flush().await?;
+        Ok::<u64, std::io::Error>(written)
+    };
+    let (fed, drained) = tokio::join!(feed, drain);
+    task.await.map_err(|e| Error::Io(std::io::Error::other(e)))??;
+    fed.map_err(Error::Io)?;
+    drained.map_err(Error::Io)
+}
+
+/// The blocking side of the input channel.
+struct ChannelReader {
+    receiver: Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl ChannelReader {
+    fn new(receiver: Receiver<Vec<u8>>) -> Self {
+        Self { receiver, chunk: vec![], pos: 0 }
+    }
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos == self.chunk.len() {
+            match self.receiver.blocking_recv() {
+                Some(chunk) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                },
+                None => return Ok(0),
+            }
+        }
+        let len = (self.chunk.len() - self.pos).min(buf.len());
+        buf[..len].copy_from_slice(&self.chunk[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+/// The blocking side of the output channel.
+struct ChannelWriter(Sender<Vec<u8>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.0.blocking_send(buf.to_vec()) {
+            Ok(_) => Ok(buf.len()),
+            Err(_) => Err(std::io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
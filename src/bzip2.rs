@@ -1,108 +1,1566 @@
 use std::io::{Read, Write};
-use crate::cli::Bzip2Cli;
-use crate::{Result};
+use sha2::Digest as _;
+use crate::cli::{Bzip2Cli, Digest};
+use crate::options::CompressionOptions;
+use crate::{Error, Result};
 
-pub(super) fn test_integrity(reader: impl Read) -> Result<u64> {
+/// The magic number starting each compressed block.
+const BLOCK_MAGIC: [u8; 6] = [0x31, 0x41, 0x59, 0x26, 0x53, 0x59];
+/// The magic number marking the end of a stream.
+const END_MAGIC: [u8; 6] = [0x17, 0x72, 0x45, 0x38, 0x50, 0x90];
+/// The length of a stream signature: `BZh`, the block size digit and the first block (or end) magic.
+const SIGNATURE_LEN: usize = 10;
+
+/// Summary of a compressed file, as shown by `--list`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(super) struct StreamInfo {
+    pub compressed: u64,
+    pub uncompressed: u64,
+    pub streams: usize,
+    /// The block size of the first stream in bytes, if it starts with a stream signature.
+    pub block_size: Option<usize>,
+}
+
+impl StreamInfo {
+    /// The space saved by compression, in percent.
+    pub fn ratio(&self) -> f64 {
+        if self.uncompressed == 0 {
+            0.0
+        } else {
+            (1.0 - self.compressed as f64 / self.uncompressed as f64) * 100.0
+        }
+    }
+
+    /// Adds the sizes and streams of `other`, for the totals of `--list`.
+    pub fn add(&mut self, other: &StreamInfo) {
+        self.compressed += other.compressed;
+        self.uncompressed += other.uncompressed;
+        self.streams += other.streams;
+    }
+}
+
+fn is_stream_signature(bytes: &[u8]) -> bool {
+    bytes.len() >= SIGNATURE_LEN
+        && &bytes[..3] == b"BZh"
+        && (b'1'..=b'9').contains(&bytes[3])
+        && (bytes[4..10] == BLOCK_MAGIC || bytes[4..10] == END_MAGIC)
+}
+
+/// Returns the block size in bytes of the stream `reader` starts with, or `None` when it does
+/// not start with a stream signature.
+pub(super) fn block_size(mut reader: impl Read) -> Result<Option<usize>> {
+    let mut header = [0u8; SIGNATURE_LEN];
+    match reader.read_exact(&mut header) {
+        Ok(_) => Ok(stream_level(&header).map(|level| level * 100_000)),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(Error::Io(e)),
+    }
+}
+
+/// Counts the bzip2 streams in `reader` by scanning for stream signatures, without decompressing.
+/// Concatenated files (e.g. produced by `pbzip2` or `bzip2 -c a b`) contain one stream per part.
+pub(super) fn stream_count(mut reader: impl Read) -> Result<usize> {
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut carry = 0;
+    let mut count = 0;
+    loop {
+        let len = reader.read(&mut buffer[carry..]).map_err(Error::Io)?;
+        if len == 0 {
+            break;
+        }
+        let end = carry + len;
+        count += buffer[..end].windows(SIGNATURE_LEN)
+            .filter(|window| is_stream_signature(window))
+            .count();
+        carry = end.min(SIGNATURE_LEN - 1);
+        buffer.copy_within(end - carry..end, 0);
+    }
+    Ok(count)
+}
+
+/// The number of bytes read and written by a compression or decompression.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(super) struct Stats {
+    pub read: u64,
+    pub written: u64,
+    /// The number of streams decoded by a decompression or written by a compression.
+    pub streams: usize,
+    /// The number of blocks whose CRC was checked by a decompression, or written by a
+    /// compression, when the backend tells them apart.
+    pub blocks: Option<usize>,
+}
+
+impl Stats {
+    /// Describes how a compression split the data, such as `3 block(s) in 3 stream(s)`.
+    pub fn chunk_summary(&self) -> String {
+        match self.blocks {
+            Some(blocks) => format!("{blocks} block(s) in {} stream(s)", self.streams),
+            None => format!("{} stream(s)", self.streams),
+        }
+    }
+
+    /// Describes the CRCs checked by a decompression, such as
+    /// `3 block CRC(s) verified, 1 combined stream CRC(s) matched`.
+    pub fn crc_summary(&self) -> String {
+        match self.blocks {
+            Some(blocks) => format!("{blocks} block CRC(s) verified, {} combined stream CRC(s) matched", self.streams),
+            None => format!("{} stream(s) decoded with all CRCs matching", self.streams),
+        }
+    }
+}
+
+/// A reader counting the bytes read through it.
+struct CountingReader<R> {
+    inner: R,
+    count: u64,
+}
+
+impl<R> CountingReader<R> {
+    fn new(inner: R) -> Self {
+        Self { inner, count: 0 }
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.count += len as u64;
+        Ok(len)
+    }
+}
+
+/// A reader reporting the cumulative number of bytes read through it to a callback.
+pub(super) struct ProgressReader<'a, R> {
+    inner: R,
+    count: u64,
+    progress: Option<&'a mut dyn FnMut(u64)>,
+}
+
+impl<'a, R> ProgressReader<'a, R> {
+    pub(super) fn new(inner: R, progress: Option<&'a mut dyn FnMut(u64)>) -> Self {
+        Self { inner, count: 0, progress }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.count += len as u64;
+        if let Some(progress) = &mut self.progress && len > 0 {
+            progress(self.count);
+        }
+        Ok(len)
+    }
+}
+
+/// A writer counting the bytes written through it.
+struct CountingWriter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W> CountingWriter<W> {
+    fn new(inner: W) -> Self {
+        Self { inner, count: 0 }
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.count += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A writer checking that the bytes written to it are the same as those of `expected`.
+pub(super) struct ComparingWriter<'a, R> {
+    expected: R,
+    name: &'a str,
+    position: u64,
+    buffer: Vec<u8>,
+}
+
+impl<'a, R: Read> ComparingWriter<'a, R> {
+    /// Creates a writer comparing against `expected`, which is called `name` in errors.
+    pub(super) fn new(expected: R, name: &'a str) -> Self {
+        Self { expected, name, position: 0, buffer: vec![] }
+    }
+
+    fn mismatch(&self, offset: u64) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, format!("decompressed data differs from {} at byte {offset}", self.name))
+    }
+
+    /// Checks that nothing of `expected` is left over.
+    pub(super) fn finish(mut self) -> std::io::Result<()> {
+        match self.expected.read(&mut [0u8; 1])? {
+            0 => Ok(()),
+            _ => Err(self.mismatch(self.position)),
+        }
+    }
+}
+
+impl<R: Read> Write for ComparingWriter<'_, R> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.resize(buf.len(), 0);
+        let mut filled = 0;
+        while filled < buf.len() {
+            match self.expected.read(&mut self.buffer[filled..])? {
+                0 => break,
+                len => filled += len,
+            }
+        }
+        if let Some(offset) = buf.iter().zip(&self.buffer[..filled]).position(|(a, b)| a != b).or((filled < buf.len()).then_some(filled)) {
+            return Err(self.mismatch(self.position + offset as u64));
+        }
+        self.position += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The running state of a `--digest` algorithm.
+enum Hasher {
+    Sha256(sha2::Sha256),
+    Crc32(crc32fast::Hasher),
+}
+
+/// A writer passing everything through to `inner` while hashing it with the `--digest`
+/// algorithm, if any.
+pub(super) struct DigestWriter<W> {
+    inner: W,
+    hasher: Option<Hasher>,
+}
+
+impl<W> DigestWriter<W> {
+    pub(super) fn new(inner: W, digest: Option<Digest>) -> Self {
+        let hasher = digest.map(|digest| match digest {
+            Digest::Sha256 => Hasher::Sha256(sha2::Sha256::new()),
+            Digest::Crc32 => Hasher::Crc32(crc32fast::Hasher::new()),
+        });
+        Self { inner, hasher }
+    }
+
+    /// Returns the inner writer and the hex digest of everything written.
+    pub(super) fn finish(self) -> (W, Option<String>) {
+        let digest = self.hasher.map(|hasher| match hasher {
+            Hasher::Sha256(hasher) => hasher.finalize().iter().map(|b| format!("{b:02x}")).collect(),
+            Hasher::Crc32(hasher) => format!("{:08x}", hasher.finalize()),
+        });
+        (self.inner, digest)
+    }
+}
+
+impl<W: Write> Write for DigestWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        match &mut self.hasher {
+            Some(Hasher::Sha256(hasher)) => hasher.update(&buf[..len]),
+            Some(Hasher::Crc32(hasher)) => hasher.update(&buf[..len]),
+            None => {},
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Estimates the memory in kilobytes needed to compress and decompress with blocks of
+/// `level` x 100k, using the formulas of the bzip2 manual: 400k + 8 x block size to compress,
+/// and 100k + 4 x block size to decompress (100k + 2.5 x block size with `--small`).
+pub(super) fn estimate_memory(level: usize, small: bool) -> (u64, u64) {
+    let block = level as u64 * 100;
+    let decompress = if small { 100 + block * 5 / 2 } else { 100 + block * 4 };
+    (400 + block * 8, decompress)
+}
+
+/// The outcome of checking each block on its own for `--test --continue`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(super) struct BlockTally {
+    pub blocks: usize,
+    pub bad: usize,
+    /// The size of the data decoded from the good blocks.
+    pub bytes: u64,
+}
+
+/// A 48-bit magic number found by a [`MagicScanner`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Magic {
+    Block,
+    End,
+}
+
+/// Looks for the block and end magic numbers in compressed data fed to it one bit at a time.
+/// Blocks are not byte aligned, so every bit offset is tried, like bzip2recover does.
+#[derive(Default)]
+struct MagicScanner {
+    window: u64,
+    /// The number of bits fed so far.
+    bits: usize,
+}
+
+impl MagicScanner {
+    const BLOCK: u64 = magic_value(&BLOCK_MAGIC);
+    const END: u64 = magic_value(&END_MAGIC);
+
+    /// Feeds the next `bit`, returning the magic number it completes, if any.
+    fn push_bit(&mut self, bit: u8) -> Option<Magic> {
+        self.window = (self.window << 1 | bit as u64) & ((1 << 48) - 1);
+        self.bits += 1;
+        match self.window {
+            _ if self.bits < 48 => None,
+            Self::BLOCK => Some(Magic::Block),
+            Self::END => Some(Magic::End),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the 48-bit value of `magic`.
+const fn magic_value(magic: &[u8; 6]) -> u64 {
+    let mut value = 0;
+    let mut index = 0;
+    while index < magic.len() {
+        value = value << 8 | magic[index] as u64;
+        index += 1;
+    }
+    value
+}
+
+/// Returns the bit offsets of the blocks in `data`.
+#[cfg(test)]
+pub(super) fn block_starts(data: &[u8]) -> Vec<usize> {
+    let mut scanner = MagicScanner::default();
+    let mut starts = vec![];
+    for bit in 0..data.len() * 8 {
+        if scanner.push_bit((data[bit / 8] >> (7 - bit % 8)) & 1) == Some(Magic::Block) {
+            starts.push(bit - 47);
+        }
+    }
+    starts
+}
+
+/// Collects bits into bytes, most significant bit first, as bzip2 streams are written.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: usize,
+}
+
+impl BitWriter {
+    fn push_bit(&mut self, bit: u8) {
+        if self.bits.is_multiple_of(8) {
+            self.bytes.push(0);
+        }
+        if let Some(byte) = self.bytes.last_mut().filter(|_| bit != 0) {
+            *byte |= 0x80 >> (self.bits % 8);
+        }
+        self.bits += 1;
+    }
+
+    /// Copies the bits `range` of `data`.
+    fn push_bits(&mut self, data: &[u8], range: std::ops::Range<usize>) {
+        for bit in range {
+            self.push_bit((data[bit / 8] >> (7 - bit % 8)) & 1);
+        }
+    }
+
+    /// Drops the bits after the first `bits`.
+    fn truncate(&mut self, bits: usize) {
+        self.bits = bits.min(self.bits);
+        self.bytes.truncate(self.bits.div_ceil(8));
+        if let Some(byte) = self.bytes.last_mut().filter(|_| !self.bits.is_multiple_of(8)) {
+            *byte &= 0xff << (8 - self.bits % 8);
+        }
+    }
+}
+
+/// Checks each block of the compressed data of `reader` on its own, so that a corrupt block
+/// does not hide the state of the blocks after it. Each block runs up to the next block or end
+/// magic number, and is wrapped into a stream of its own, whose combined CRC is the block CRC,
+/// and decoded. Only the block being read is held in memory.
+pub(super) fn check_blocks(reader: impl Read, cli: &Bzip2Cli) -> std::io::Result<BlockTally> {
+    let mut reader = RetryReader(reader);
+    let mut scanner = MagicScanner::default();
+    let mut tally = BlockTally::default();
+    // the bit offset and the bits of the block being read, from its magic number on.
+    let mut block: Option<(usize, BitWriter)> = None;
+    let mut buf = vec![0u8; cli.buffer_bytes()];
+    loop {
+        let len = reader.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        for &byte in &buf[..len] {
+            for shift in (0..8).rev() {
+                let bit = (byte >> shift) & 1;
+                if let Some((_, bits)) = block.as_mut() {
+                    bits.push_bit(bit);
+                }
+                let Some(magic) = scanner.push_bit(bit) else {
+                    continue;
+                };
+                let start = scanner.bits - 48;
+                if let Some((block_start, mut bits)) = block.take() {
+                    // the magic number ending the block has been copied into it.
+                    bits.truncate(start - block_start);
+                    check_block(block_start, &bits, &mut tally, cli);
+                }
+                if magic == Magic::Block {
+                    let mut bits = BitWriter::default();
+                    bits.push_bits(&BLOCK_MAGIC, 0..48);
+                    block = Some((start, bits));
+                }
+            }
+        }
+    }
+    if let Some((start, bits)) = block {
+        check_block(start, &bits, &mut tally, cli);
+    }
+    Ok(tally)
+}
+
+/// Checks the block starting at bit `start`, whose bits from its magic number on are `block`,
+/// and adds it to `tally`.
+fn check_block(start: usize, block: &BitWriter, tally: &mut BlockTally, cli: &Bzip2Cli) {
+    tally.blocks += 1;
+    // the block magic is followed by the 32-bit block CRC.
+    if block.bits < 80 {
+        tally.bad += 1;
+        return;
+    }
+    let mut stream = BitWriter::default();
+    // the largest block size, so that a block of any size fits.
+    stream.push_bits(b"BZh9", 0..32);
+    stream.push_bits(&block.bytes, 0..block.bits);
+    stream.push_bits(&END_MAGIC, 0..48);
+    stream.push_bits(&block.bytes, 48..80);
+    match test_integrity(stream.bytes.as_slice(), cli) {
+        Ok(bytes) => tally.bytes += bytes,
+        Err(e) => {
+            log::warn!("block {} at byte {}: {e}", tally.blocks, start / 8);
+            tally.bad += 1;
+        },
+    }
+}
+
+/// Reads into `buf` like `Read::read`, retrying reads interrupted by a signal.
+fn read_retry(reader: &mut impl Read, buf: &mut [u8]) -> std::io::Result<usize> {
+    loop {
+        match reader.read(buf) {
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            result => return result,
+        }
+    }
+}
+
+/// A reader retrying reads interrupted by a signal, so that they are not taken for failures
+/// by the decoders.
+struct RetryReader<R>(R);
+
+impl<R: Read> Read for RetryReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        read_retry(&mut self.0, buf)
+    }
+}
+
+/// A reader or writer throttled to `--limit-rate` bytes per second by a token bucket holding
+/// up to a second's worth of bytes.
+struct RateLimited<T> {
+    inner: T,
+    rate: u64,
+    tokens: f64,
+    last: std::time::Instant,
+}
+
+impl<T> RateLimited<T> {
+    fn new(inner: T, rate: u64) -> Self {
+        Self { inner, rate, tokens: rate as f64, last: std::time::Instant::now() }
+    }
+
+    /// Returns how many of `len` bytes may be transferred at once.
+    fn chunk(&self, len: usize) -> usize {
+        match self.rate {
+            0 => len,
+            rate => len.min(rate as usize).max(1),
+        }
+    }
+
+    /// Takes `len` bytes from the bucket, waiting when it runs dry.
+    fn take(&mut self, len: usize) {
+        if self.rate == 0 {
+            return;
+        }
+        let now = std::time::Instant::now();
+        let rate = self.rate as f64;
+        self.tokens = (self.tokens + now.duration_since(self.last).as_secs_f64() * rate).min(rate) - len as f64;
+        self.last = now;
+        if self.tokens < 0.0 {
+            std::thread::sleep(std::time::Duration::from_secs_f64(-self.tokens / rate));
+        }
+    }
+}
+
+impl<R: Read> Read for RateLimited<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.chunk(buf.len());
+        let len = self.inner.read(&mut buf[..len])?;
+        self.take(len);
+        Ok(len)
+    }
+}
+
+impl<W: Write> Write for RateLimited<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = self.chunk(buf.len());
+        let len = self.inner.write(&buf[..len])?;
+        self.take(len);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Copies all bytes from `reader` to `writer` through a buffer of `buffer_size` bytes,
+/// retrying interrupted reads and writes, and returns the number of bytes copied.
+fn copy_retry(reader: &mut impl Read, writer: &mut impl Write, buffer_size: usize) -> std::io::Result<u64> {
+    let mut buffer = vec![0u8; buffer_size];
+    let mut total = 0u64;
+    loop {
+        let len = read_retry(reader, &mut buffer)?;
+        if len == 0 {
+            break;
+        }
+        writer.write_all(&buffer[..len])?;
+        total += len as u64;
+    }
+    writer.flush()?;
+    Ok(total)
+}
+
+/// Describes the block size declared by the stream starting at `header`, such as
+/// `block size 900k` for `BZh9`, or returns `None` when `header` is not a stream signature.
+pub(super) fn block_size_line(header: &[u8]) -> Option<String> {
+    stream_level(header).map(|level| format!("block size {level}00k"))
+}
+
+/// Returns the block size in units of 100k declared by the stream starting at `header`.
+fn stream_level(header: &[u8]) -> Option<usize> {
+    is_stream_signature(header).then(|| (header[3] - b'0') as usize)
+}
+
+/// Describes the memory `--small` decodes a stream with blocks of `level` x 100k within.
+#[cfg(feature = "sys")]
+pub(super) fn small_mode_line(level: usize) -> String {
+    format!("small mode: decoding within {}k", estimate_memory(level, true).1)
+}
+
+/// The number of bytes `Streams` reads ahead to look for the next stream signature.
+const STREAMS_CHUNK: usize = 16 * 1024;
+/// The number of bytes `Streams` keeps of the end of each stream, enough for a name trailer.
+const TAIL_LEN: usize = 4 * 1024;
+
+/// The failure of decoding data with trailing garbage under `--ignore-trailing-garbage=false`.
+#[derive(Debug)]
+struct TrailingGarbage;
+
+impl std::fmt::Display for TrailingGarbage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "trailing garbage after EOF")
+    }
+}
+
+impl std::error::Error for TrailingGarbage {}
+
+/// A failure of decoding corrupt data, with where in the input it happened.
+#[derive(Debug)]
+struct CorruptAt {
+    source: std::io::Error,
+    /// The number of bytes of the input the decoder had consumed.
+    offset: u64,
+    /// The offset of the block being decoded, when its magic number was found.
+    block: Option<u64>,
+}
+
+impl CorruptAt {
+    /// Adds the position to `e` when it is due to the data rather than to reading it.
+    fn wrap(e: std::io::Error, offset: u64, block: Option<u64>) -> std::io::Error {
+        let kind = e.kind();
+        let garbage = e.get_ref().is_some_and(|inner| inner.is::<TrailingGarbage>());
+        match kind {
+            std::io::ErrorKind::InvalidData | std::io::ErrorKind::UnexpectedEof if !garbage => {
+                std::io::Error::new(kind, CorruptAt { source: e, offset, block })
+            },
+            _ => e,
+        }
+    }
+}
+
+impl std::fmt::Display for CorruptAt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl std::error::Error for CorruptAt {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// Converts a failure of decoding into an `Error`, telling corrupt data from I/O errors.
+fn decode_error(e: std::io::Error) -> Error {
+    match e.get_ref().and_then(|inner| inner.downcast_ref::<TrailingGarbage>()) {
+        Some(garbage) => Error::CorruptData(garbage.to_string()),
+        None if e.kind() == std::io::ErrorKind::Unsupported => Error::InvalidInput(format!("bzip2: {e}")),
+        None => Error::Io(e),
+    }
+}
+
+/// Tells whether `stream` ends with a stream footer: the end magic and the combined CRC,
+/// padded to a whole byte. The footer is not byte aligned, so each possible padding is tried.
+pub(super) fn ends_with_footer(stream: &[u8]) -> bool {
+    let bit = |index: usize| (stream[index / 8] >> (7 - index % 8)) & 1;
+    let bits = stream.len() * 8;
+    (0..8).filter(|padding| bits >= 80 + padding).any(|padding| {
+        let start = bits - padding - 80;
+        (0..48).fold(0u64, |magic, i| magic << 1 | bit(start + i) as u64)
+            == END_MAGIC.iter().fold(0u64, |magic, &byte| magic << 8 | byte as u64)
+    })
+}
+
+/// Splits concatenated bzip2 streams at their signatures, since `bzip2_rs` stops
+/// at the end of the first stream. Reading yields the bytes of the current stream only.
+/// The name trailer of `--name` after a stream is skipped. Any other data after a stream is
+/// trailing garbage, which ends the input like in bzip2.
+struct Streams<R> {
+    inner: R,
+    buffer: Vec<u8>,
+    pos: usize,
+    end: usize,
+    eof: bool,
+    at_boundary: bool,
+    stream_start: bool,
+    started: bool,
+    /// The last bytes read from the current stream, to check how it ends.
+    tail: Vec<u8>,
+    /// Set when trailing garbage ended the input.
+    garbage: bool,
+    /// The number of bytes read so far, which is the offset of the next one in the input.
+    offset: u64,
+    /// The offset of the current stream in the input.
+    stream_offset: u64,
+    /// Finds the blocks of the current stream, whose offsets are kept in `block_offsets`.
+    scanner: MagicScanner,
+    block_offsets: Vec<u64>,
+}
+
+impl<R: Read> Streams<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            buffer: Vec::with_capacity(STREAMS_CHUNK),
+            pos: 0,
+            end: 0,
+            eof: false,
+            at_boundary: false,
+            stream_start: false,
+            started: false,
+            tail: vec![],
+            garbage: false,
+            offset: 0,
+            stream_offset: 0,
+            scanner: MagicScanner::default(),
+            block_offsets: vec![],
+        }
+    }
+
+    /// Returns the offset of the last block of the current stream starting before `offset`.
     #[cfg(feature = "sys")]
-    {
-        libbzip2::test_integrity(reader)
+    fn block_before(&self, offset: u64) -> Option<u64> {
+        self.block_offsets.iter().copied().take_while(|&block| block < offset).last()
     }
-#[cfg(not(feature = "sys"))]
-    {
-        pure_rust::test_integrity(reader)
+
+    /// Skips the rest of the current stream and moves to the start of the next one.
+    /// Returns `false` when the input is exhausted.
+    fn next_stream(&mut self) -> std::io::Result<bool> {
+        if self.started {
+            let mut discard = [0u8; 1024];
+            let mut skipped = 0;
+            loop {
+                let len = self.read(&mut discard)?;
+                if len == 0 {
+                    break;
+                }
+                skipped += len;
+            }
+            if skipped > 0 {
+                log::trace!("skipped {skipped} bytes after the end of a stream");
+            }
+            let tail = std::mem::take(&mut self.tail);
+            let stream_len = tail.len() - crate::trailer::trailer_len(&tail).unwrap_or(0);
+            if !ends_with_footer(&tail[..stream_len]) {
+                self.garbage = true;
+                return Ok(false);
+            }
+        }
+        self.started = true;
+        self.stream_start = true;
+        self.stream_offset = self.offset;
+        self.scanner = MagicScanner::default();
+        self.block_offsets.clear();
+        if self.buffer.len() - self.pos < SIGNATURE_LEN {
+            self.refill()?;
+        } else {
+            self.scan();
+        }
+        Ok(self.pos < self.buffer.len())
+    }
+
+    /// Reports trailing garbage that ended the input as `--ignore-trailing-garbage` says: by
+    /// default with a warning, not at all when set, and as corrupt data when set to false.
+    fn report_garbage(&self, cli: &Bzip2Cli) -> std::io::Result<()> {
+        match cli.ignore_trailing_garbage {
+            _ if !self.garbage => Ok(()),
+            None => {
+                log::warn!("bzip2: trailing garbage after EOF ignored");
+                Ok(())
+            },
+            Some(true) => Ok(()),
+            Some(false) => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, TrailingGarbage)),
+        }
+    }
+
+    /// Returns the buffered bytes at the start of the current stream, right after `next_stream`.
+    fn header(&self) -> &[u8] {
+        &self.buffer[self.pos..]
+    }
+
+    fn refill(&mut self) -> std::io::Result<()> {
+        self.buffer.drain(..self.pos);
+        self.pos = 0;
+        while !self.eof && self.buffer.len() < STREAMS_CHUNK {
+            let len = self.buffer.len();
+            self.buffer.resize(STREAMS_CHUNK, 0);
+            match self.inner.read(&mut self.buffer[len..]) {
+                Ok(read) => {
+                    self.buffer.truncate(len + read);
+                    self.eof = read == 0;
+                },
+                Err(e) => {
+                    self.buffer.truncate(len);
+                    return Err(e);
+                },
+            }
+        }
+        self.scan();
+        Ok(())
+    }
+
+    /// Finds how far the current stream extends within the buffered bytes.
+    fn scan(&mut self) {
+        let skip = usize::from(self.stream_start);
+        let next = self.buffer[self.pos..].windows(SIGNATURE_LEN)
+            .skip(skip)
+            .position(is_stream_signature);
+        self.at_boundary = next.is_some();
+        self.end = match next {
+            Some(index) => self.pos + skip + index,
+            None if self.eof => self.buffer.len(),
+            None => self.buffer.len().saturating_sub(SIGNATURE_LEN - 1).max(self.pos),
+        };
     }
 }
 
-pub(super) fn compress(reader: impl Read, writer: impl Write, cli: &Bzip2Cli) -> Result<u64> {
+impl<R: Read> Read for Streams<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos == self.end {
+            if self.at_boundary || self.eof {
+                return Ok(0);
+            }
+            self.refill()?;
+        }
+        let len = (self.end - self.pos).min(buf.len());
+        buf[..len].copy_from_slice(&self.buffer[self.pos..self.pos + len]);
+        self.tail.extend_from_slice(&buf[..len]);
+        if self.tail.len() > 2 * TAIL_LEN {
+            self.tail.drain(..self.tail.len() - TAIL_LEN);
+        }
+        for &byte in &buf[..len] {
+            for shift in (0..8).rev() {
+                if self.scanner.push_bit((byte >> shift) & 1) == Some(Magic::Block) {
+                    self.block_offsets.push(self.stream_offset + (self.scanner.bits as u64 - 48) / 8);
+                }
+            }
+        }
+        self.offset += len as u64;
+        self.pos += len;
+        if len > 0 {
+            self.stream_start = false;
+        }
+        Ok(len)
+    }
+}
+
+/// Decodes `reader` without writing the data anywhere. Corrupt data is reported with the
+/// number of bytes the decoder consumed when decoding broke and the offset of the block it
+/// was decoding, as `corrupt at offset N in the block at offset M`.
+pub(super) fn test_integrity(reader: impl Read, cli: &Bzip2Cli) -> Result<u64> {
+    let reader = RetryReader(reader);
     #[cfg(feature = "sys")]
-    {
-        libbzip2::compress(reader, writer, cli)
+    let result = libbzip2::test_integrity(reader, cli);
+    #[cfg(not(feature = "sys"))]
+    let result = pure_rust::test_integrity(reader, cli);
+    result.map_err(corrupt_at)
+}
+
+/// Adds where decoding broke to a failure of decoding corrupt data.
+fn corrupt_at(e: Error) -> Error {
+    let Error::Io(e) = e else {
+        return e;
+    };
+    match e.get_ref().and_then(|inner| inner.downcast_ref::<CorruptAt>()) {
+        Some(CorruptAt { source, offset, block: Some(block) }) => {
+            Error::CorruptData(format!("{source}, corrupt at offset {offset} in the block at offset {block}"))
+        },
+        Some(CorruptAt { source, offset, block: None }) => Error::CorruptData(format!("{source}, corrupt at offset {offset}")),
+        None if matches!(e.kind(), std::io::ErrorKind::InvalidData | std::io::ErrorKind::UnexpectedEof) => Error::CorruptData(e.to_string()),
+        None => Error::Io(e),
     }
+}
+
+/// Compresses `reader` into `writer`. `--limit-rate` throttles reading the uncompressed data.
+pub(super) fn compress(reader: impl Read, writer: impl Write + Send, options: &CompressionOptions) -> Result<Stats> {
+    let reader = RateLimited::new(RetryReader(reader), options.limit_rate);
+    let mut writer = CountingWriter::new(writer);
+    #[cfg(feature = "sys")]
+    let stats = libbzip2::compress(reader, &mut writer, options)?;
+    #[cfg(not(feature = "sys"))]
+    let stats = pure_rust::compress(reader, &mut writer, options)?;
+    log::info!("compressed into {} of up to {}00k", stats.chunk_summary(), options.level);
+    Ok(Stats { written: writer.count, ..stats })
+}
+
+/// Decompresses `reader` into `writer`. `--limit-rate` throttles writing the decompressed data.
+///
+/// `writer` is not buffered here: the decoders write whole blocks (pure Rust) or chunks of
+/// `--buffer-size` (libbzip2), which a `BufWriter` would only copy once more before passing
+/// them on, and they flush `writer` once the data ends. Callers writing to stdout buffer it
+/// themselves, as it is line buffered.
+pub(super) fn decompress(reader: impl Read, writer: impl Write, cli: &Bzip2Cli) -> Result<Stats> {
+    let mut reader = CountingReader::new(RetryReader(reader));
+    let writer = RateLimited::new(writer, cli.limit_rate);
+    #[cfg(feature = "sys")]
+    let stats = libbzip2::decompress(&mut reader, writer, cli)?;
+    #[cfg(not(feature = "sys"))]
+    let stats = pure_rust::decompress(&mut reader, writer, cli)?;
+    log::info!("{}", stats.crc_summary());
+    Ok(Stats { read: reader.count, ..stats })
+}
+
+/// Decompresses `reader` one block at a time.
 #[cfg(not(feature = "sys"))]
+pub(super) fn blocks<R: Read>(reader: R, cli: &Bzip2Cli) -> impl Iterator<Item = Result<Vec<u8>>> + use<R> {
+    pure_rust::Blocks::new(RetryReader(reader), cli).map(|block| block.map_err(decode_error))
+}
+
+/// Describes the backend compressing and decompressing the data, for `--version`.
+pub(super) fn backend() -> String {
+    #[cfg(feature = "sys")]
+    {
+        // SAFETY: libbzip2 returns a pointer to its static, NUL-terminated version string.
+        let version = unsafe { std::ffi::CStr::from_ptr(libbz2_rs_sys::BZ2_bzlibVersion()) };
+        format!("libbzip2 {}", version.to_string_lossy())
+    }
+    #[cfg(not(feature = "sys"))]
     {
-        pure_rust::compress(reader, writer, cli)
+        "pure Rust (banzai encoder, bzip2-rs decoder)".to_string()
     }
 }
 
-pub(super) fn decompress(reader: impl Read, writer: impl Write, cli: &Bzip2Cli) -> Result<u64> {
+/// The outcome of compressing the same data with one backend for `--benchmark`.
+#[cfg(feature = "both")]
+pub(super) struct Benchmark {
+    pub backend: &'static str,
+    pub compressed: Vec<u8>,
+    pub elapsed: std::time::Duration,
+}
+
+/// Compresses `data` with each backend in memory, timing it and checking that its output
+/// decompresses back to `data`.
+#[cfg(feature = "both")]
+pub(super) fn benchmark(data: &[u8], cli: &Bzip2Cli) -> Result<Vec<Benchmark>> {
+    type Backend = fn(&[u8], &mut Vec<u8>, &CompressionOptions) -> Result<Stats>;
+    let options = CompressionOptions::from(cli);
+    let backends: [(&'static str, Backend); 2] = [
+        ("libbzip2", |data, out, options| libbzip2::compress(data, out, options)),
+        ("pure_rust", |data, out, options| pure_rust::compress(data, out, options)),
+    ];
+    backends.into_iter().map(|(backend, compress)| {
+        let mut compressed = vec![];
+        let start = std::time::Instant::now();
+        compress(data, &mut compressed, &options)?;
+        let elapsed = start.elapsed();
+        let mut decompressed = vec![];
+        libbzip2::decompress(compressed.as_slice(), &mut decompressed, cli)?;
+        if decompressed != data {
+            return Err(Error::InvalidInput(format!("bzip2: the output of {backend} does not decompress to its input")));
+        }
+        Ok(Benchmark { backend, compressed, elapsed })
+    }).collect()
+}
+
+/// A push-style bzip2 encoder, compressing everything written to it into an inner writer.
+pub trait Encode<W: Write>: Write {
+    /// Writes the end of the compressed data and returns the inner writer.
+    fn finish(self) -> std::io::Result<W>;
+}
+
+/// Creates a push-style encoder writing the compressed data to `writer`, with `options` given
+/// as [`CompressionOptions`] or a [`Bzip2Cli`].
+pub fn encoder<W: Write>(writer: W, options: impl Into<CompressionOptions>) -> impl Encode<W> {
+    let options = options.into();
     #[cfg(feature = "sys")]
     {
-        libbzip2::decompress(reader, writer, cli)
+        libbzip2::encoder(writer, &options)
     }
-#[cfg(not(feature = "sys"))]
+    #[cfg(not(feature = "sys"))]
     {
-        pure_rust::decompress(reader, writer, cli)
+        pure_rust::warn_unsupported(&options);
+        pure_rust::Encoder::new(writer, &options)
     }
 }
 
+#[cfg(all(test, not(feature = "sys")))]
+pub(super) use pure_rust::decode_blocks;
+
 #[cfg(feature = "sys")]
 mod libbzip2 {
-    use bzip2::{read::MultiBzDecoder, write::BzEncoder, Compression};
+    use bzip2::{Action, Compress, Compression, Decompress, Status};
     use std::io::{sink, Read, Write};
 
     use crate::{Error, Result};
     use crate::cli::Bzip2Cli;
+    use crate::options::CompressionOptions;
+    use super::{block_size_line, copy_retry, decode_error, small_mode_line, stream_level, CorruptAt, Encode, Stats, Streams};
 
-    pub(super) fn test_integrity(reader: impl Read) -> Result<u64> {
-        let mut decoder = MultiBzDecoder::new(reader);
-        match std::io::copy(&mut decoder, &mut sink()) {
-            Ok(bytes) => Ok(bytes),
-            Err(e) => Err(Error::Io(e)),
+    /// The work factor libbzip2 uses when none is given.
+    const DEFAULT_WORK_FACTOR: u32 = 30;
+    /// The size of the buffer compressed data is staged in before being written.
+    const OUTPUT_SIZE: usize = 32 * 1024;
+
+    /// A push-style encoder on top of libbzip2. `BzEncoder` always uses the default work
+    /// factor, so the stream is driven directly to honour `--workfactor`.
+    pub(super) struct Encoder<W: Write> {
+        compress: Compress,
+        writer: W,
+        output: Vec<u8>,
+    }
+
+    impl<W: Write> Encoder<W> {
+        /// Feeds `input` to libbzip2 with `action` and writes whatever it produced.
+        /// Returns the number of consumed bytes and the resulting status.
+        fn step(&mut self, input: &[u8], action: Action) -> std::io::Result<(usize, Status)> {
+            let (before_in, before_out) = (self.compress.total_in(), self.compress.total_out());
+            let status = self.compress.compress(input, &mut self.output, action)
+                .map_err(std::io::Error::other)?;
+            let produced = (self.compress.total_out() - before_out) as usize;
+            self.writer.write_all(&self.output[..produced])?;
+            Ok(((self.compress.total_in() - before_in) as usize, status))
         }
     }
 
-    pub(super) fn decompress(reader: impl Read, writer: impl Write, _cli: &Bzip2Cli) -> Result<u64> {
-        let mut decoder = MultiBzDecoder::new(reader);
-        match std::io::copy(&mut decoder, &mut std::io::BufWriter::new(writer)) {
-            Ok(bytes) => Ok(bytes),
-            Err(e) => Err(Error::Io(e)),
+    impl<W: Write> Write for Encoder<W> {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            loop {
+                let (consumed, _) = self.step(buf, Action::Run)?;
+                if consumed > 0 || buf.is_empty() {
+                    return Ok(consumed);
+                }
+            }
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            while let (_, Status::FlushOk) = self.step(&[], Action::Flush)? {}
+            self.writer.flush()
+        }
+    }
+
+    impl<W: Write> Encode<W> for Encoder<W> {
+        fn finish(mut self) -> std::io::Result<W> {
+            while let (_, Status::FinishOk) = self.step(&[], Action::Finish)? {}
+            self.writer.flush()?;
+            Ok(self.writer)
+        }
+    }
+
+    pub(super) fn encoder<W: Write>(writer: W, options: &CompressionOptions) -> Encoder<W> {
+        if options.flush_interval.is_some() {
+            log::warn!("bzip2: --flush-interval is not supported by libbzip2 and is ignored");
+        }
+        let level = Compression::new(options.level as u32);
+        Encoder {
+            compress: Compress::new(level, options.work_factor.unwrap_or(DEFAULT_WORK_FACTOR)),
+            writer,
+            output: vec![0u8; OUTPUT_SIZE],
+        }
+    }
+
+    /// Decodes all concatenated streams of `reader` into `writer`. libbzip2 checks the CRCs
+    /// without telling the blocks apart.
+    fn decode(reader: impl Read, writer: &mut impl Write, cli: &Bzip2Cli) -> std::io::Result<Stats> {
+        let mut streams = Streams::new(std::io::BufReader::with_capacity(cli.buffer_bytes(), reader));
+        let mut input = vec![0u8; cli.buffer_bytes()];
+        let mut output = vec![0u8; cli.buffer_bytes()];
+        let mut total = 0;
+        let mut count = 0;
+        while streams.next_stream()? {
+            count += 1;
+            log::trace!("stream {count}: start");
+            if let Some(line) = block_size_line(streams.header()) {
+                log::info!("stream {count}: {line}");
+            }
+            // libbzip2 switches to its low-memory algorithm whatever the block size is.
+            if let Some(level) = stream_level(streams.header()).filter(|_| cli.small) {
+                log::info!("stream {count}: {}", small_mode_line(level));
+            }
+            let mut decompress = Decompress::new(cli.small);
+            total += decode_stream(&mut streams, &mut decompress, &mut input, &mut output, writer).map_err(|e| {
+                // libbzip2 consumes the input as it decodes it, a few bits ahead at most.
+                let offset = streams.stream_offset + decompress.total_in();
+                CorruptAt::wrap(e, offset, streams.block_before(offset))
+            })?;
+        }
+        if count == 0 {
+            let e = std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "compressed file ends unexpectedly");
+            return Err(CorruptAt::wrap(e, streams.offset, None));
+        }
+        streams.report_garbage(cli)?;
+        writer.flush()?;
+        Ok(Stats { written: total, streams: count, ..Stats::default() })
+    }
+
+    /// Decodes the current stream of `streams` into `writer`, returning the size of the data.
+    fn decode_stream<R: Read>(streams: &mut Streams<R>, decompress: &mut Decompress, input: &mut [u8], output: &mut [u8], writer: &mut impl Write) -> std::io::Result<u64> {
+        let mut total = 0;
+        let (mut start, mut end) = (0, 0);
+        loop {
+            if start == end {
+                (start, end) = (0, streams.read(input)?);
+            }
+            let (before_in, before_out) = (decompress.total_in(), decompress.total_out());
+            let status = decompress.decompress(&input[start..end], output)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+            let consumed = (decompress.total_in() - before_in) as usize;
+            let produced = (decompress.total_out() - before_out) as usize;
+            let exhausted = start == end;
+            start += consumed;
+            writer.write_all(&output[..produced])?;
+            total += produced as u64;
+            if status == Status::StreamEnd {
+                return Ok(total);
+            }
+            if exhausted && consumed == 0 && produced == 0 {
+                return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "compressed file ends unexpectedly"));
+            }
         }
     }
 
-    pub(super) fn compress(reader: impl Read, writer: impl Write, cli: &Bzip2Cli) -> Result<u64>{
-        let level = cli.compress_level() as u32;
-        let compression = Compression::new(level);
-        let mut encoder = BzEncoder::new(writer, compression);
-        match std::io::copy(&mut std::io::BufReader::new(reader), &mut encoder) {
+    pub(super) fn test_integrity(reader: impl Read, cli: &Bzip2Cli) -> Result<u64> {
+        match decode(reader, &mut sink(), cli) {
+            Ok(stats) => Ok(stats.written),
+            Err(e) => Err(decode_error(e)),
+        }
+    }
+
+    pub(super) fn decompress(reader: impl Read, mut writer: impl Write, cli: &Bzip2Cli) -> Result<Stats> {
+        match decode(reader, &mut writer, cli) {
             Ok(bytes) => Ok(bytes),
+            Err(e) => Err(decode_error(e)),
+        }
+    }
+
+    /// Compresses `reader` into a single stream, whose blocks libbzip2 does not count.
+    pub(super) fn compress(reader: impl Read, writer: impl Write, options: &CompressionOptions) -> Result<Stats> {
+        let mut encoder = encoder(writer, options);
+        let mut reader = std::io::BufReader::with_capacity(options.buffer_size, reader);
+        // finish explicitly, so that the stream is complete before anything else is appended to the writer.
+        match copy_retry(&mut reader, &mut encoder, options.buffer_size).and_then(|bytes| encoder.finish().map(|_| bytes)) {
+            Ok(read) => Ok(Stats { read, streams: 1, ..Stats::default() }),
             Err(e) => Err(Error::Io(e)),
         }
     }
 }
 
 
-#[cfg(not(feature = "sys"))]
+#[cfg(any(not(feature = "sys"), feature = "both"))]
+#[cfg_attr(feature = "sys", allow(dead_code))]
 mod pure_rust {
-    use bzip2_rs::DecoderReader;
+    use bzip2_rs::decoder::{Decoder, ReadState};
     use std::io::{sink, Read, Write};
+    use std::sync::mpsc::{RecvTimeoutError, SyncSender};
+    use std::time::{Duration, Instant};
 
     use crate::{Error, Result};
     use crate::cli::Bzip2Cli;
+    use crate::options::CompressionOptions;
+    use super::{copy_retry, decode_error, CorruptAt, Encode, Stats, Streams};
 
-    pub(super) fn test_integrity(reader: impl Read) -> Result<u64> {
-        let mut decoder = DecoderReader::new(reader);
-        match std::io::copy(&mut decoder, &mut sink()) {
-            Ok(bytes) => Ok(bytes),
-            Err(e) => Err(Error::Io(e)),
+    /// A push-style encoder for the pure Rust backend. `banzai` only encodes whole readers, so
+    /// the written data is collected into blocks and each full block is written as an
+    /// independent stream; bzip2 decoders read such concatenated streams transparently.
+    /// With `--flush-interval`, a block collected for longer than the interval is written
+    /// and flushed when more data arrives, even though it is not full; [`compress`] also
+    /// writes it out while waiting for the input.
+    pub struct Encoder<W: Write> {
+        writer: W,
+        level: usize,
+        block: Vec<u8>,
+        streams: usize,
+        flush_interval: Option<Duration>,
+        /// When the first byte of the block being collected arrived.
+        collecting_since: Instant,
+    }
+
+    impl<W: Write> Encoder<W> {
+        pub fn new(writer: W, options: &CompressionOptions) -> Self {
+            let level = options.level;
+            Self {
+                writer,
+                level,
+                block: Vec::with_capacity(level * 100_000),
+                streams: 0,
+                flush_interval: options.flush_interval,
+                collecting_since: Instant::now(),
+            }
+        }
+
+        /// Tells whether the collected block has waited longer than `--flush-interval`.
+        fn is_overdue(&self) -> bool {
+            self.time_left().is_some_and(|left| left.is_zero())
+        }
+
+        /// Returns how long the collected block may still wait before it is overdue, or `None`
+        /// when there is no block or no `--flush-interval`.
+        fn time_left(&self) -> Option<Duration> {
+            self.flush_interval.filter(|_| !self.block.is_empty())
+                .map(|interval| interval.saturating_sub(self.collecting_since.elapsed()))
+        }
+
+        /// Writes and flushes the collected block when it is overdue.
+        fn write_overdue(&mut self) -> std::io::Result<()> {
+            if self.is_overdue() {
+                self.write_block()?;
+                self.writer.flush()?;
+            }
+            Ok(())
+        }
+
+        fn block_size(&self) -> usize {
+            self.level * 100_000
+        }
+
+        /// Writes the collected block as a complete stream.
+        fn write_block(&mut self) -> std::io::Result<()> {
+            banzai::encode(self.block.as_slice(), std::io::BufWriter::new(&mut self.writer), self.level)?;
+            self.block.clear();
+            self.streams += 1;
+            Ok(())
+        }
+    }
+
+    impl<W: Write> Write for Encoder<W> {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let len = (self.block_size() - self.block.len()).min(buf.len());
+            if self.block.is_empty() {
+                self.collecting_since = Instant::now();
+            }
+            self.block.extend_from_slice(&buf[..len]);
+            if self.block.len() == self.block_size() {
+                self.write_block()?;
+            } else {
+                self.write_overdue()?;
+            }
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.writer.flush()
+        }
+    }
+
+    impl<W: Write> Encoder<W> {
+        /// Writes the last block and flushes the writer, returning the number of streams
+        /// written, each holding one block.
+        fn close(&mut self) -> std::io::Result<usize> {
+            // an empty input still needs one (empty) stream.
+            if !self.block.is_empty() || self.streams == 0 {
+                self.write_block()?;
+            }
+            self.writer.flush()?;
+            Ok(self.streams)
+        }
+    }
+
+    impl<W: Write> Encode<W> for Encoder<W> {
+        fn finish(mut self) -> std::io::Result<W> {
+            self.close()?;
+            Ok(self.writer)
+        }
+    }
+
+    /// The maximum number of bytes passed to the decoder at once.
+    const DECODER_INPUT_SIZE: usize = 1024;
+
+    /// Decodes all concatenated streams of `reader` into `writer`, counting the blocks whose
+    /// CRC the decoder checked.
+    fn decode(reader: impl Read, writer: &mut impl Write, cli: &Bzip2Cli) -> std::io::Result<Stats> {
+        let mut blocks = Blocks::new(reader, cli);
+        let mut count = 0;
+        let written = write_blocks(&mut blocks, writer, &mut |stream, block, bytes| {
+            count += 1;
+            log::trace!("stream {stream}, block {block}: {bytes} bytes");
+        })?;
+        Ok(Stats { written, streams: blocks.stream, blocks: Some(count), ..Stats::default() })
+    }
+
+    /// Decodes all concatenated streams of `reader` into `writer`, calling `on_block` with the
+    /// stream number, block number (both starting at 1) and uncompressed size of each block.
+    #[cfg(test)]
+    pub(crate) fn decode_blocks(reader: impl Read, writer: &mut impl Write, cli: &Bzip2Cli, on_block: &mut dyn FnMut(usize, usize, u64)) -> std::io::Result<u64> {
+        write_blocks(&mut Blocks::new(reader, cli), writer, on_block)
+    }
+
+    /// Writes the blocks decoded by `blocks` to `writer`, calling `on_block` for each of them.
+    fn write_blocks<R: Read>(blocks: &mut Blocks<R>, writer: &mut impl Write, on_block: &mut dyn FnMut(usize, usize, u64)) -> std::io::Result<u64> {
+        let mut total = 0;
+        while let Some(block) = blocks.next_block()? {
+            writer.write_all(&block)?;
+            total += block.len() as u64;
+            on_block(blocks.stream, blocks.block, block.len() as u64);
+        }
+        writer.flush()?;
+        Ok(total)
+    }
+
+    /// Classifies a failure of the decoder as corrupt data.
+    fn corrupt(e: bzip2_rs::decoder::DecoderError) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+    }
+
+    /// Decodes concatenated streams one block at a time, so that only a single decompressed
+    /// block is held in memory.
+    pub(crate) struct Blocks<R: Read> {
+        streams: Streams<std::io::BufReader<R>>,
+        decoder: Option<Decoder>,
+        cli: Bzip2Cli,
+        output: Vec<u8>,
+        data: Vec<u8>,
+        /// The space the decoder asked for when the block before was yielded.
+        pending: Option<usize>,
+        empty_reads: usize,
+        /// The number of the current stream, starting at 1.
+        stream: usize,
+        /// The number of the last block yielded from the current stream, starting at 1.
+        block: usize,
+        done: bool,
+    }
+
+    impl<R: Read> Blocks<R> {
+        pub(crate) fn new(reader: R, cli: &Bzip2Cli) -> Self {
+            Self {
+                streams: Streams::new(std::io::BufReader::with_capacity(cli.buffer_bytes(), reader)),
+                decoder: None,
+                cli: cli.clone(),
+                output: vec![0u8; cli.buffer_bytes()],
+                data: vec![],
+                pending: None,
+                empty_reads: 0,
+                stream: 0,
+                block: 0,
+                done: false,
+            }
+        }
+
+        /// Moves to the next stream, returning `false` when there is none.
+        fn start_stream(&mut self) -> std::io::Result<bool> {
+            // the decoder always holds a whole block, so it cannot stay within the memory of --small.
+            if self.cli.small {
+                return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "--small needs bzip2rs built with the `sys` feature"));
+            }
+            if !self.streams.next_stream()? {
+                if self.stream == 0 {
+                    return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "compressed file ends unexpectedly"));
+                }
+                self.streams.report_garbage(&self.cli)?;
+                return Ok(false);
+            }
+            self.stream += 1;
+            self.block = 0;
+            self.empty_reads = 0;
+            if let Some(line) = super::block_size_line(self.streams.header()) {
+                log::info!("stream {}: {line}", self.stream);
+            }
+            self.decoder = Some(Decoder::new());
+            Ok(true)
+        }
+
+        /// Passes up to `space` bytes of the current stream to the decoder.
+        fn feed(&mut self, space: usize) -> std::io::Result<()> {
+            let mut input = [0u8; DECODER_INPUT_SIZE];
+            let len = self.streams.read(&mut input[..space.min(DECODER_INPUT_SIZE)])?;
+            self.empty_reads = if len == 0 { self.empty_reads + 1 } else { 0 };
+            // the end of input is signalled twice at most: to read the last block and the stream footer.
+            if self.empty_reads > 2 {
+                return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "compressed file ends unexpectedly"));
+            }
+            if let Some(decoder) = self.decoder.as_mut() {
+                decoder.write(&input[..len]).map_err(corrupt)?;
+            }
+            Ok(())
+        }
+
+        /// Takes the decoded block, counting it.
+        fn take_block(&mut self) -> Vec<u8> {
+            self.block += 1;
+            std::mem::take(&mut self.data)
+        }
+
+        /// Decodes the next block, or returns `None` after the last one. Corrupt data is
+        /// reported with where decoding broke: the decoder is fed ahead of what it decoded,
+        /// by less than `DECODER_INPUT_SIZE` bytes.
+        pub(crate) fn next_block(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+            self.decode_block().map_err(|e| {
+                let block = self.streams.block_offsets.get(self.block).copied();
+                CorruptAt::wrap(e, self.streams.offset, block)
+            })
+        }
+
+        fn decode_block(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+            if let Some(space) = self.pending.take() {
+                self.feed(space)?;
+            }
+            loop {
+                let Some(decoder) = self.decoder.as_mut() else {
+                    if !self.start_stream()? {
+                        return Ok(None);
+                    }
+                    continue;
+                };
+                match decoder.read(&mut self.output).map_err(corrupt)? {
+                    ReadState::NeedsWrite(space) => {
+                        // the decoder only asks for more input between blocks.
+                        if !self.data.is_empty() {
+                            self.pending = Some(space);
+                            return Ok(Some(self.take_block()));
+                        }
+                        self.feed(space)?;
+                    },
+                    ReadState::Read(len) => {
+                        self.data.extend_from_slice(&self.output[..len]);
+                        self.empty_reads = 0;
+                    },
+                    ReadState::Eof => {
+                        self.decoder = None;
+                        if !self.data.is_empty() {
+                            return Ok(Some(self.take_block()));
+                        }
+                    },
+                }
+            }
+        }
+    }
+
+    impl<R: Read> Iterator for Blocks<R> {
+        type Item = std::io::Result<Vec<u8>>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            if self.done {
+                return None;
+            }
+            let block = self.next_block().transpose();
+            self.done = !matches!(block, Some(Ok(_)));
+            block
         }
     }
 
-    pub(super) fn decompress(reader: impl Read, writer: impl Write, _cli: &Bzip2Cli) -> Result<u64> {
-        let mut decoder = DecoderReader::new(reader);
-        match std::io::copy(&mut decoder, &mut std::io::BufWriter::new(writer)) {
+    pub(super) fn test_integrity(reader: impl Read, cli: &Bzip2Cli) -> Result<u64> {
+        match decode(reader, &mut sink(), cli) {
+            Ok(stats) => Ok(stats.written),
+            Err(e) => Err(decode_error(e)),
+        }
+    }
+
+    pub(super) fn decompress(reader: impl Read, mut writer: impl Write, cli: &Bzip2Cli) -> Result<Stats> {
+        match decode(reader, &mut writer, cli) {
             Ok(bytes) => Ok(bytes),
-            Err(e) => Err(Error::Io(e)),
+            Err(e) => Err(decode_error(e)),
+        }
+    }
+
+    /// Warns about options libbzip2 supports but `banzai` does not.
+    pub(super) fn warn_unsupported(options: &CompressionOptions) {
+        if options.work_factor.is_some() {
+            log::warn!("bzip2: --workfactor is not supported by the pure Rust backend and is ignored");
+        }
+        if options.small {
+            log::warn!("bzip2: --small is not supported by the pure Rust backend, blocks are encoded with the usual memory");
         }
     }
 
-    pub(super) fn compress(reader: impl Read, writer: impl Write, cli: &Bzip2Cli) -> Result<u64>{
-        let level = cli.compress_level();
-        let mut reader = std::io::BufReader::new(reader);
-        let writer = std::io::BufWriter::new(writer);
-        match banzai::encode(&mut reader, writer, level) {
-            Ok(bytes) => Ok(bytes as u64),
+    /// A writer counting the blocks in the compressed data written through it.
+    struct BlockCounter<W> {
+        inner: W,
+        scanner: super::MagicScanner,
+        blocks: usize,
+    }
+
+    impl<W: Write> Write for BlockCounter<W> {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let len = self.inner.write(buf)?;
+            for &byte in &buf[..len] {
+                for shift in (0..8).rev() {
+                    if self.scanner.push_bit((byte >> shift) & 1) == Some(super::Magic::Block) {
+                        self.blocks += 1;
+                    }
+                }
+            }
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    /// Compresses `reader` into one stream per block.
+    pub(super) fn compress(reader: impl Read, writer: impl Write + Send, options: &CompressionOptions) -> Result<Stats> {
+        warn_unsupported(options);
+        let level = options.level;
+        let mut reader = std::io::BufReader::with_capacity(options.buffer_size, reader);
+        // the blocks are counted in the output, as banzai does not tell how many it wrote.
+        let counter = BlockCounter { inner: writer, scanner: super::MagicScanner::default(), blocks: 0 };
+        let mut writer = std::io::BufWriter::with_capacity(options.buffer_size, counter);
+        // the streams of the threads are only written in batches, which --flush-interval bounds.
+        let result = if options.flush_interval.is_some() {
+            compress_timed(&mut reader, &mut writer, options)
+        } else if options.threads > 1 {
+            compress_parallel(&mut reader, &mut writer, level, options.threads)
+        } else {
+            // one block at a time, so that memory stays bounded for endless inputs such as pipes.
+            let mut encoder = Encoder::new(&mut writer, options);
+            copy_retry(&mut reader, &mut encoder, options.buffer_size)
+                .and_then(|bytes| encoder.close().map(|streams| (bytes, streams)))
+        };
+        match result.and_then(|counts| writer.flush().map(|_| counts)) {
+            Ok((read, streams)) => Ok(Stats { read, streams, blocks: Some(writer.get_ref().blocks), ..Stats::default() }),
             Err(e) => Err(Error::Io(e)),
         }
     }
 
+    /// The number of chunks of input waiting for the encoder of [`compress_timed`].
+    const CHUNK_DEPTH: usize = 4;
+
+    /// Compresses `reader` with `--flush-interval`. The input is read on this thread and
+    /// encoded on another one, which writes out an overdue block while the next read is still
+    /// blocked on a slow input. Returns the number of bytes read and of streams written.
+    fn compress_timed(reader: &mut impl Read, writer: &mut (impl Write + Send), options: &CompressionOptions) -> std::io::Result<(u64, usize)> {
+        let (sender, receiver) = std::sync::mpsc::sync_channel::<Vec<u8>>(CHUNK_DEPTH);
+        std::thread::scope(|scope| {
+            let encoding = scope.spawn(move || {
+                let mut encoder = Encoder::new(writer, options);
+                loop {
+                    let received = match encoder.time_left() {
+                        Some(left) => receiver.recv_timeout(left),
+                        None => receiver.recv().map_err(RecvTimeoutError::from),
+                    };
+                    match received {
+                        Ok(chunk) => encoder.write_all(&chunk)?,
+                        Err(RecvTimeoutError::Timeout) => encoder.write_overdue()?,
+                        Err(RecvTimeoutError::Disconnected) => return encoder.close(),
+                    }
+                }
+            });
+            let mut chunks = ChunkSender(sender);
+            let read = copy_retry(reader, &mut chunks, options.buffer_size);
+            // closes the channel, so that the encoder finishes once it has caught up.
+            drop(chunks);
+            let streams = encoding.join().unwrap_or_else(|_| Err(std::io::Error::other("compression thread panicked")))?;
+            read.map(|read| (read, streams))
+        })
+    }
+
+    /// Passes everything written to it to the encoder of [`compress_timed`].
+    struct ChunkSender(SyncSender<Vec<u8>>);
+
+    impl Write for ChunkSender {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            // the encoder only hangs up when it failed, which its own error reports.
+            self.0.send(buf.to_vec()).map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))?;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Compresses `reader` like pbzip2: each block-sized chunk becomes an independent stream,
+    /// `threads` chunks are compressed at a time, and the streams are written in input order.
+    /// Returns the number of bytes read and of streams written.
+    fn compress_parallel(reader: &mut impl Read, writer: &mut impl Write, level: usize, threads: usize) -> std::io::Result<(u64, usize)> {
+        let chunk_size = level * 100_000;
+        let mut total = 0;
+        let mut streams = 0;
+        loop {
+            let mut chunks = vec![];
+            while chunks.len() < threads {
+                let mut chunk = Vec::with_capacity(chunk_size);
+                reader.by_ref().take(chunk_size as u64).read_to_end(&mut chunk)?;
+                if chunk.is_empty() {
+                    break;
+                }
+                chunks.push(chunk);
+            }
+            // an empty input still needs one (empty) stream.
+            if chunks.is_empty() && streams == 0 {
+                chunks.push(vec![]);
+            }
+            let last = chunks.len() < threads;
+            let compressed = std::thread::scope(|scope| {
+                let handles = chunks.iter()
+                    .map(|chunk| scope.spawn(move || {
+                        let mut stream = vec![];
+                        banzai::encode(chunk.as_slice(), std::io::BufWriter::new(&mut stream), level)?;
+                        Ok(stream)
+                    }))
+                    .collect::<Vec<_>>();
+                handles.into_iter()
+                    .map(|handle| handle.join().unwrap_or_else(|_| Err(std::io::Error::other("compression thread panicked"))))
+                    .collect::<Vec<_>>()
+            });
+            for stream in compressed {
+                writer.write_all(&stream?)?;
+                streams += 1;
+            }
+            total += chunks.iter().map(|chunk| chunk.len() as u64).sum::<u64>();
+            if last {
+                break;
+            }
+        }
+        writer.flush()?;
+        Ok((total, streams))
+    }
+
 }
\ No newline at end of file
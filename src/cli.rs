@@ -1,78 +1,304 @@
-#[derive(clap::Parser, Debug)]
+use std::io::{IsTerminal, Write};
+
+#[derive(clap::Parser, Clone, Debug)]
 #[command(
     version, propagate_version = true,
     about = "A bzip2-compatible command line interface",
     disable_version_flag = true
 )]
-pub(crate) struct Bzip2Cli {
+pub struct Bzip2Cli {
     #[clap(short, long, help = "force decompression")]
     pub decompress: bool,
-    #[clap(short ='z', long, help = "force compression")]
+    #[clap(short ='z', long, conflicts_with_all = ["decompress", "test"], help = "force compression")]
     pub compress: bool,
-    #[clap(short, long, help = "keep (don't delete) input files")]
+    #[clap(long, conflicts_with_all = ["decompress", "compress", "test", "list"], help = "decompress the files with a compressed suffix and compress the others, in one run")]
+    pub auto: bool,
+    #[clap(short, long, visible_alias = "no-delete", help = "keep (don't delete) input files")]
     pub keep: bool,
+    #[clap(long, conflicts_with = "keep", help = "delete input files after processing them, which must be asked for when BZIP2RS_SAFE is set")]
+    pub delete: bool,
     #[clap(short, long, help = "overwrite existing output files")]
     pub force: bool,
-    #[clap(short, long, help = "test compressed file integrity")]
-    pub test: bool,
+    #[clap(long, conflicts_with_all = ["decompress", "test", "list", "stdout"], help = "append the compressed stream to an existing output file instead of refusing it")]
+    pub append: bool,
+    #[clap(short, long, help = "prompt before overwriting existing output files")]
+    pub interactive: bool,
+    #[clap(short, long, action = clap::ArgAction::Count, help = "test compressed file integrity (a 2nd -t also compares with the uncompressed file next to it)")]
+    pub test: u8,
+    #[clap(long = "continue", requires = "test", help = "with -t, check every block instead of stopping at the first bad one, and report how many are bad")]
+    pub continue_on_error: bool,
+    #[clap(short, long, help = "list compressed file information")]
+    pub list: bool,
+    #[clap(long, requires = "list", help = "with -l, print the listing as JSON")]
+    pub json: bool,
     #[clap(short = 'c', long, help = "output to standard out")]
     pub stdout: bool,
-    #[clap(short, long, help = "suppress noncritical error messages")]
-    pub quiet: bool,
-    #[clap(short, long, default_value = "0", help = "be verbose (a 2nd -v gives more)")]
+    #[clap(short, long, action = clap::ArgAction::Count, help = "suppress noncritical error messages (a 2nd -q suppresses all of them, leaving only the exit status)")]
+    pub quiet: u8,
+    #[clap(short, long, action = clap::ArgAction::Count, help = "be verbose (a 2nd -v gives more)")]
     pub verbose: u8,
     #[clap(short = 'L', long, help = "display software version & license")]
     pub license: bool,
-    #[clap(short = 'V', long, help = "display software version & license")]
+    #[clap(short = 'V', long, help = "display software version, backend & license")]
     pub version: bool,
-    #[clap(short, long, help = "use less memory (at most 2500k)")]
+    #[clap(short, long, help = "use less memory (at most 2500k) when decompressing, which needs the sys feature")]
     pub small: bool,
     #[clap(short, long, help = "set block size to 100k .. 900k")]
     pub block_size: Option<u8>,
+    #[clap(long, value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(100_000..=900_000), help = "set block size in bytes (100000 .. 900000), rounded to the nearest 100k")]
+    pub blocksize_bytes: Option<usize>,
+    #[clap(long, value_name = "N", value_parser = clap::value_parser!(u8).range(1..=9), help = "set the compression level 1 .. 9, a clearer alias for --block-size that overrides the other level options")]
+    pub level: Option<u8>,
+    #[clap(long, help = "use the smallest block size holding each regular input file in a single block, overriding the other level options")]
+    pub auto_level: bool,
+    #[clap(long = "workfactor", value_name = "N", value_parser = clap::value_parser!(u32).range(0..=250), help = "set the effort spent on repetitive data before falling back (0 .. 250, default 30)")]
+    pub work_factor: Option<u32>,
     #[clap(long, help = "alias for -1")]
     pub fast: bool,
     #[clap(long, help = "alias for -9")]
     pub best: bool,
-    #[arg(index = 1, help = "input file(s)")]
+    #[clap(long, value_name = "KB", default_value = "64", help = "set I/O buffer size in kilobytes")]
+    pub buffer_size: usize,
+    #[clap(long, value_name = "BOOL", default_value = "true", num_args = 0..=1, default_missing_value = "true", action = clap::ArgAction::Set, help = "fsync output files before deleting the originals")]
+    pub sync: bool,
+    #[clap(long, value_name = "N", default_value = "1", help = "number of worker threads (0 for one per available CPU)")]
+    pub threads: usize,
+    #[clap(long, value_name = "BYTES_PER_SEC", default_value = "0", help = "limit reading the data to compress, or writing the decompressed data, to BYTES_PER_SEC (0 for no limit)")]
+    pub limit_rate: u64,
+    #[clap(long, value_name = "SECONDS", value_parser = parse_interval, help = "when compressing, write out the current block after SECONDS even if it is not full, for following slow inputs such as logs")]
+    pub flush_interval: Option<f64>,
+    #[clap(long, value_name = "N", default_value = "0", help = "retry opening and creating files up to N times on transient I/O errors, with exponential backoff")]
+    pub retries: u32,
+    #[clap(long, value_enum, value_name = "WHAT", value_delimiter = ',', num_args = 0..=1, require_equals = true, default_missing_value = "mode,time,owner", help = "do not copy the given metadata of input files to their outputs (all of it without a list)")]
+    pub no_preserve: Vec<Preserve>,
+    #[clap(long, help = "create sparse output files on decompression (Unix only)")]
+    pub sparse: bool,
+    #[clap(long, help = "memory-map regular input files instead of reading them when compressing (Unix only); the inputs must not be truncated meanwhile")]
+    pub mmap: bool,
+    #[clap(long, value_name = "PATH", conflicts_with_all = ["test", "list"], help = "compress the contents of PATH before each input, or strip them when decompressing; such files are not restored as is by other bzip2 tools")]
+    pub prepend: Option<String>,
+    #[clap(long, help = "expand wildcards in input file names not expanded by the shell (always done on Windows)")]
+    pub glob: bool,
+    #[clap(short, long, help = "operate recursively on directories")]
+    pub recursive: bool,
+    #[clap(long, value_name = "N", requires = "recursive", help = "with -r, descend at most N directory levels")]
+    pub max_depth: Option<usize>,
+    #[clap(long, value_name = "GLOB", requires = "recursive", help = "with -r, only process files whose names match GLOB")]
+    pub include: Vec<String>,
+    #[clap(long, value_name = "GLOB", requires = "recursive", help = "with -r, skip files and directories whose names match GLOB")]
+    pub exclude: Vec<String>,
+    #[clap(short = 'S', long, value_name = "SUF", help = "use suffix SUF on compressed files instead of .bz2; a comma-separated list is tried in order when decompressing, and its first suffix is used when compressing")]
+    pub suffix: Option<String>,
+    #[clap(long, value_name = "BOOL", num_args = 0..=1, default_missing_value = "true", action = clap::ArgAction::Set, help = "ignore data after the last stream without a warning, or with false, fail on it as corrupt")]
+    pub ignore_trailing_garbage: Option<bool>,
+    #[clap(long, help = "keep the partial output of a failed run, renamed to OUTPUT.broken")]
+    pub keep_broken: bool,
+    #[clap(long, help = "fail to decompress a file without a known compressed suffix, instead of writing FILE.out")]
+    pub strict_suffix: bool,
+    #[clap(long, help = "when decompressing to stdout, write each decoded chunk at once instead of collecting --buffer-size of them")]
+    pub unbuffered: bool,
+    #[clap(long, value_name = "DIR", num_args = 0..=1, require_equals = true, help = "write each output file into DIR first, or into $TMPDIR without =DIR, and move it into place once complete")]
+    pub temp_dir: Option<Option<String>>,
+    #[clap(long, value_name = "RATIO", help = "keep a file uncompressed, removing its output, when compressing it gives more than RATIO of its size (such as 0.95)")]
+    pub ratio_threshold: Option<f64>,
+    #[clap(long, conflicts_with = "append", help = "append the CRC32 of the original data after the compressed data, which is checked when decompressing and testing; only one file can be written to stdout with it")]
+    pub checksum_trailer: bool,
+    #[clap(long, help = "skip input files whose contents are already bzip2, gzip, zstd or xz data, whatever their names")]
+    pub skip_compressed: bool,
+    #[clap(short = 'N', long, help = "store the original file name when compressing, and restore it when decompressing")]
+    pub name: bool,
+    #[clap(long, value_name = "DIR", help = "write output files into DIR instead of next to the inputs (implies -k)")]
+    pub output_dir: Option<String>,
+    #[clap(long, value_name = "TEMPLATE", value_parser = crate::template::validate, conflicts_with_all = ["output_dir", "output"], help = "name each output file after TEMPLATE, where {name}, {stem}, {ext} and {dir} stand for the parts of its input file")]
+    pub output_template: Option<String>,
+    #[clap(long, help = "create the missing directories of output files, instead of failing")]
+    pub make_dirs: bool,
+    #[clap(short = 'o', long, value_name = "PATH", conflicts_with_all = ["output_dir", "append"], help = "write the output of the single input file to PATH, or to stdout when PATH is - (implies -k)")]
+    pub output: Option<String>,
+    #[clap(long, conflicts_with_all = ["decompress", "test", "list"], help = "compare compressing the input files with both backends, without writing any file (needs the `both` feature)")]
+    pub benchmark: bool,
+    #[clap(long, help = "compress and decompress built-in data with the backend in use, printing PASS or FAIL for each")]
+    pub self_test: bool,
+    #[clap(long, conflicts_with_all = ["decompress", "test", "list", "benchmark", "auto"], help = "decompress the first of two input files and compare it with the second, reporting where they first differ")]
+    pub compare: bool,
+    #[clap(long, value_name = "PATH", help = "append log messages to PATH instead of writing them to stderr")]
+    pub log_file: Option<String>,
+    #[clap(long, help = "prefix log messages with an RFC 3339 timestamp (always done with -vv)")]
+    pub log_timestamps: bool,
+    #[clap(long, help = "print the size and processing time of each file at the end")]
+    pub time: bool,
+    #[clap(long, value_enum, value_name = "ALGO", help = "print a digest of the decompressed data of each tested file")]
+    pub digest: Option<Digest>,
+    #[clap(long, value_name = "SHELL", hide = true, help = "print a completion script for SHELL")]
+    pub completion: Option<clap_complete::Shell>,
+    // options may follow the files like in bzip2, so no `trailing_var_arg`: files named like
+    // options are given after `--`, which clap takes as the end of the options.
+    #[arg(index = 1, help = "input file(s); give files whose names start with - after --")]
     pub input_files: Vec<String>,
 }
 
-pub(crate) enum Mode {
+/// The digest algorithms of `--digest`.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Digest {
+    Sha256,
+    Crc32,
+}
+
+/// The metadata of input files copied to their outputs, unless excluded by `--no-preserve`.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Preserve {
+    Mode,
+    Time,
+    Owner,
+}
+
+/// Writes the completion script of `shell` for `bzip2rs` to `out`.
+pub fn write_completion(shell: clap_complete::Shell, out: &mut impl std::io::Write) {
+    let mut command = <Bzip2Cli as clap::CommandFactory>::command();
+    clap_complete::generate(shell, &mut command, env!("CARGO_PKG_NAME"), out);
+}
+
+/// Parses the SECONDS of `--flush-interval`, which must be a positive number.
+fn parse_interval(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(seconds) if seconds > 0.0 && seconds.is_finite() => Ok(seconds),
+        Ok(_) => Err("must be a positive number of seconds".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// The full license text printed for `--license`.
+const LICENSE: &str = include_str!("../LICENSE");
+
+impl Default for Bzip2Cli {
+    /// The settings of running `bzip2rs` without any options.
+    fn default() -> Self {
+        <Self as clap::Parser>::parse_from([env!("CARGO_PKG_NAME")])
+    }
+}
+
+pub enum Mode {
     Compress,
     Decompress,
     Test,
+    List,
+    Benchmark,
+    Compare,
 }
 
-fn init_logger(cli: &Bzip2Cli) {
-    unsafe {
-        if cli.quiet {
-            std::env::set_var("RUST_LOG", "error");
-        } else if cli.verbose >= 2 {
-            std::env::set_var("RUST_LOG", "trace");
-        } else if cli.verbose == 1 {
-            std::env::set_var("RUST_LOG", "info");
-        } else {
-            std::env::set_var("RUST_LOG", "warn");
+/// Tells whether log lines are colored: only on a terminal, and never when `NO_COLOR` is set
+/// to a non-empty value (see <https://no-color.org>).
+pub(crate) fn color_enabled(no_color: Option<&std::ffi::OsStr>, terminal: bool) -> bool {
+    terminal && no_color.is_none_or(|value| value.is_empty())
+}
+
+/// Colors `line` by `level` when `color` is set: errors in red and warnings in yellow.
+pub(crate) fn colorize(level: log::Level, line: &str, color: bool) -> String {
+    match level {
+        log::Level::Error if color => format!("\x1b[31m{line}\x1b[0m"),
+        log::Level::Warn if color => format!("\x1b[33m{line}\x1b[0m"),
+        _ => line.to_string(),
+    }
+}
+
+/// Configures the logger at the level of `-q` and `-v`, whatever `RUST_LOG` says, writing to
+/// the `--log-file` or to stderr. Also returns the warning to log when the log file cannot be
+/// opened, in which case stderr is used instead.
+pub(crate) fn logger(cli: &Bzip2Cli) -> (env_logger::Builder, Option<String>) {
+    let mut builder = env_logger::Builder::new();
+    let mut warning = None;
+    let mut color = color_enabled(std::env::var_os("NO_COLOR").as_deref(), std::io::stderr().is_terminal());
+    if let Some(path) = &cli.log_file {
+        match std::fs::OpenOptions::new().create(true).append(true).open(path) {
+            Ok(file) => {
+                builder.target(env_logger::Target::Pipe(Box::new(file)));
+                color = false;
+            },
+            Err(e) => warning = Some(format!("bzip2: cannot open log file {path}: {e}; logging to stderr")),
         }
     }
-    env_logger::try_init().unwrap_or_else(|_| {
-        eprintln!("failed to initialize logger. set RUST_LOG to see logs.");
+    let timestamps = cli.log_timestamps || cli.verbosity() >= 2;
+    builder.filter_level(cli.level_filter())
+        .write_style(if color { env_logger::WriteStyle::Always } else { env_logger::WriteStyle::Never })
+        .format(move |buf, record| {
+            let line = if timestamps {
+                format!("[{} {:5} {}] {}", buf.timestamp(), record.level(), record.target(), record.args())
+            } else {
+                format!("[{:5} {}] {}", record.level(), record.target(), record.args())
+            };
+            writeln!(buf, "{}", colorize(record.level(), &line, color))
+        });
+    (builder, warning)
+}
+
+/// Installs the logger without touching the environment.
+fn init_logger(cli: &Bzip2Cli) {
+    let (mut builder, warning) = logger(cli);
+    builder.try_init().unwrap_or_else(|_| {
+        eprintln!("failed to initialize logger.");
     });
-    log::info!("set log level to {}", std::env::var("RUST_LOG").unwrap_or_else(|_| "unknown".to_string()));
+    if let Some(warning) = warning {
+        log::warn!("{warning}");
+    }
+    log::info!("set log level to {}", cli.log_level());
 }
 
 impl Bzip2Cli {
     pub fn init(&self) -> bool {
-        if self.license || self.version {
-            println!("bzip2rs {}", env!("CARGO_PKG_VERSION"));
-            println!("Copyright (C) 2026 by Haruaki Tamada");
-            println!("License: MIT");
+        if let Some(banner) = self.banner() {
+            print!("{banner}");
+            return true;
+        }
+        if let Some(shell) = self.completion {
+            write_completion(shell, &mut std::io::stdout());
             return true;
         }
         init_logger(self);
         false
     }
 
+    /// Returns the text printed for `--version`, including the backend in use, or the full
+    /// license for `--license`.
+    pub fn banner(&self) -> Option<String> {
+        let version = format!("bzip2rs {}\nCopyright (C) 2026 by Haruaki Tamada\n", env!("CARGO_PKG_VERSION"));
+        if self.license {
+            Some(format!("{version}\n{LICENSE}"))
+        } else if self.version {
+            Some(format!("{version}Backend: {}\nLicense: MIT\n", crate::bzip2::backend()))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the verbosity `-q` and `-v` net out to: each `-v` raises it by one and each `-q`
+    /// lowers it by one, so `-q -vv` is the same as `-v` wherever the flags come from.
+    pub fn verbosity(&self) -> i8 {
+        self.verbose.min(i8::MAX as u8) as i8 - self.quiet.min(i8::MAX as u8) as i8
+    }
+
+    /// Tells whether `-q` is given at all.
+    pub fn is_quiet(&self) -> bool {
+        self.quiet > 0
+    }
+
+    /// Returns the log level for the effective verbosity: at -2 or below nothing is shown,
+    /// at -1 only errors, zero adds warnings, one adds progress information and two or more
+    /// trace everything.
+    pub fn log_level(&self) -> &'static str {
+        match self.verbosity() {
+            ..-1 => "off",
+            -1 => "error",
+            0 => "warn",
+            1 => "info",
+            _ => "trace",
+        }
+    }
+
+    /// Returns the log level of `log_level` as a filter for the logger.
+    pub fn level_filter(&self) -> log::LevelFilter {
+        self.log_level().parse().unwrap_or(log::LevelFilter::Warn)
+    }
+
     pub fn is_empty(&self) -> bool {
         self.input_files.is_empty()
     }
@@ -81,28 +307,114 @@ impl Bzip2Cli {
         self.input_files.iter()
     }
 
+    /// Tells whether the output goes to stdout: with `-c`, as `bzcat`, or with `-o -`.
     pub fn is_stdout(&self, program_name: &str) -> bool {
-        self.stdout || program_name == "bzcat"
+        self.stdout || program_name == "bzcat" || self.output.as_deref() == Some("-")
+    }
+
+    /// Tells whether input files are deleted after processing them: when they are not kept,
+    /// and in the safe mode enabled by setting `BZIP2RS_SAFE`, only with `--delete`.
+    pub fn should_delete(&self, program_name: &str) -> bool {
+        let safe = std::env::var_os("BZIP2RS_SAFE").is_some_and(|value| !value.is_empty() && value != "0");
+        self.deletes(program_name, safe)
+    }
+
+    /// Tells whether input files are deleted, `safe` telling whether safe mode is enabled.
+    pub(crate) fn deletes(&self, program_name: &str, safe: bool) -> bool {
+        !self.keep(program_name) && (self.delete || !safe)
+    }
+
+    /// Tells whether input files are kept after processing them. Besides `--keep`, this is the
+    /// case when writing to stdout (including as `bzcat`), to `--output` or into `--output-dir`,
+    /// and always in the modes writing no output file, whatever other flags are given.
+    pub fn keep(&self, program_name: &str) -> bool {
+        let writes_output = matches!(self.mode(program_name), Mode::Compress | Mode::Decompress);
+        !writes_output || self.keep || self.is_stdout(program_name) || self.output.is_some() || self.output_dir.is_some()
+    }
+
+    /// Returns the number of worker threads, resolving `--threads 0` to the available parallelism.
+    pub fn resolved_threads(&self) -> usize {
+        match self.threads {
+            0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            threads => threads,
+        }
+    }
+
+    /// Tells whether `what` is copied from input files to their outputs.
+    pub fn preserves(&self, what: Preserve) -> bool {
+        !self.no_preserve.contains(&what)
+    }
+
+    /// Returns the suffixes given to `--suffix`, which takes a comma-separated list.
+    pub fn custom_suffixes(&self) -> Vec<&str> {
+        self.suffix.iter().flat_map(|suffixes| suffixes.split(','))
+            .filter(|suffix| !suffix.is_empty())
+            .collect()
     }
 
+    /// Returns the suffix appended to compressed files: the first of `--suffix`, or `.bz2`.
+    pub fn compressed_suffix(&self) -> &str {
+        self.custom_suffixes().first().copied().unwrap_or(".bz2")
+    }
+
+    /// Returns the path the output derived from an input file is written to, which is
+    /// `dest` itself unless `--output` or `--output-dir` is given.
+    pub fn output_path(&self, dest: &str) -> String {
+        match (self.output.as_deref(), &self.output_dir, std::path::Path::new(dest).file_name()) {
+            (Some(output), _, _) if output != "-" => output.to_string(),
+            (_, Some(dir), Some(name)) => std::path::Path::new(dir).join(name).to_string_lossy().into_owned(),
+            _ => dest.to_string(),
+        }
+    }
+
+    /// Returns the directory output files are written to before being moved into place with
+    /// `--temp-dir`: the given one, or the temporary directory of the system (`$TMPDIR`).
+    pub fn temp_dir(&self) -> Option<std::path::PathBuf> {
+        self.temp_dir.as_ref().map(|dir| dir.as_ref().map_or_else(std::env::temp_dir, std::path::PathBuf::from))
+    }
+
+    pub fn buffer_bytes(&self) -> usize {
+        self.buffer_size.max(1) * 1024
+    }
+
+    /// Returns the compression level, which is the block size in units of 100k. The options
+    /// setting it take precedence in this order: `--level`, then the explicit block size of
+    /// `--blocksize-bytes` or `--block-size`, then `--fast` or `--best`, and the default is 6.
     pub fn compress_level(&self) -> usize {
-        if self.fast {
+        if let Some(level) = self.level {
+            level as usize
+        } else if let Some(bytes) = self.blocksize_bytes {
+            // both backends take the block size in units of 100k.
+            (bytes + 50_000) / 100_000
+        } else if let Some(level) = self.block_size {
+            level as usize
+        } else if self.fast {
             1
         } else if self.best {
             9
-        } else if let Some(level) = self.block_size {
-            level as usize
         } else {
             6
         }
     }
 
+    /// Returns the level of `--auto-level` for an input of `size` bytes: the smallest one whose
+    /// block holds the whole input, or 9 for inputs larger than a single block.
+    pub fn auto_level(size: u64) -> u8 {
+        size.div_ceil(100_000).clamp(1, 9) as u8
+    }
+
     pub fn mode(&self, program_name: &str) -> Mode {
-        if self.decompress || program_name == "bunzip2" || program_name == "bzcat" {
-            Mode::Decompress
-        } else if self.test {
+        if self.list {
+            Mode::List
+        } else if self.benchmark {
+            Mode::Benchmark
+        } else if self.compare {
+            Mode::Compare
+        } else if self.test > 0 {
+            // testing wins over decompressing, so that `-dt` and `bunzip2 -t` write nothing.
             Mode::Test
-        } else if self.input_files.iter().all(|f| f.ends_with(".bz2") || f.ends_with(".tbz2") || f.ends_with(".tbz") || f.ends_with(".tz2")) {
+        } else if self.decompress || program_name == "bunzip2" || program_name == "bzcat"
+            || self.input_files.iter().all(|f| crate::suffix::compressed_suffix(f, &self.custom_suffixes()).is_some()) {
             Mode::Decompress
         } else {
             Mode::Compress
@@ -0,0 +1,3483 @@
+//! A Rust implementation of the bzip2 compression/decompression tool.
+//!
+//! Besides the `bzip2rs` command, this crate provides in-memory helpers such as
+//! [`compress_to_vec`] and [`decompress_to_vec`] for embedding it in other programs.
+
+use std::fmt::Display;
+use std::io::{BufRead, IsTerminal, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+
+pub mod cli;
+mod bzip2;
+mod options;
+#[cfg(feature = "async")]
+mod async_io;
+#[cfg(unix)]
+mod mmap;
+mod prepend;
+#[cfg(unix)]
+mod sparse;
+mod suffix;
+mod template;
+mod trailer;
+mod walk;
+
+#[cfg(feature = "async")]
+pub use async_io::{compress_async, decompress_async};
+
+pub use bzip2::{encoder, Encode};
+pub use options::CompressionOptions;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    Array(Vec<Error>),
+    Io(std::io::Error),
+    CannotWriteToStdout(),
+    FileExists(String),
+    AlreadyCompressed(String, String),
+    InvalidInput(String),
+    NoSpaceLeft(String),
+    /// The compressed data is corrupt, which bzip2 reports with exit code 2.
+    CorruptData(String),
+    /// Noncritical failures that `--quiet` only logged; they still fail the run.
+    Suppressed(usize),
+    /// Any failure under `-qq`, which is only told by the exit status.
+    Silenced(Box<Error>),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Array(errs) => {
+                for err in errs {
+                    writeln!(f, "{err}")?;
+                }
+                Ok(())
+            },
+            Error::Io(e) => write!(f, "I/O error: {e}"),
+            Error::FileExists(file) => write!(f, "bzip2: Output file {file} already exists."),
+            Error::AlreadyCompressed(file, suffix) => write!(f, "bzip2: Input file {file} already has {suffix} suffix."),
+            Error::InvalidInput(msg) => write!(f, "{msg}"),
+            Error::CannotWriteToStdout() => write!(f, "bzip2: I won't write compressed data to a terminal. Use -c for redirecting the output to a file."),
+            Error::NoSpaceLeft(dest) => write!(f, "bzip2: No space left on device while writing {dest}; partial output removed and input file kept."),
+            Error::CorruptData(msg) => write!(f, "bzip2: compressed data is corrupt: {msg}"),
+            Error::Suppressed(count) => write!(f, "bzip2: {count} file(s) skipped."),
+            Error::Silenced(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    fn is_no_space(&self) -> bool {
+        matches!(self, Error::Io(e) if e.kind() == std::io::ErrorKind::StorageFull)
+    }
+
+    /// Returns the exit code of the command for this failure: 2 for corrupt data, like bzip2,
+    /// and 1 otherwise.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Array(errs) => errs.iter().map(Error::exit_code).max().unwrap_or(1),
+            Error::CorruptData(_) => 2,
+            Error::Silenced(e) => e.exit_code(),
+            _ => 1,
+        }
+    }
+
+    /// Tells whether this failure only skips a file, which bzip2 treats as noncritical.
+    fn is_noncritical(&self) -> bool {
+        matches!(self, Error::FileExists(_))
+    }
+
+    /// Tells whether nothing needs to be printed for this failure, as with `--quiet`.
+    pub fn is_suppressed(&self) -> bool {
+        matches!(self, Error::Suppressed(_) | Error::Silenced(_))
+    }
+
+    /// Routes the noncritical failures through the logger for `--quiet`, leaving only the
+    /// fatal ones to be reported by the caller.
+    fn quieted(self) -> Error {
+        let errs = match self {
+            Error::Array(errs) => errs,
+            e => vec![e],
+        };
+        let (noncritical, critical): (Vec<Error>, Vec<Error>) = errs.into_iter().partition(Error::is_noncritical);
+        for e in &noncritical {
+            log::warn!("{e}");
+        }
+        match Error::error_or((), critical) {
+            Err(e) => e,
+            Ok(()) => Error::Suppressed(noncritical.len()),
+        }
+    }
+
+    fn error_or<T>(ok_item: T, errs: Vec<Error>) -> Result<T> {
+        if errs.is_empty() {
+            Ok(ok_item)
+        } else if errs.len() == 1 {
+            Err(errs.into_iter().next().unwrap())
+        } else {
+            Err(Error::Array(errs))
+        }
+    }
+}
+
+/// The result of compressing or decompressing a batch of files: how many succeeded, the
+/// failures of the others, and the bytes read and written for the compressed files.
+#[derive(Debug, Default)]
+struct BatchOutcome {
+    ok: usize,
+    failed: Vec<Error>,
+    read: u64,
+    written: u64,
+}
+
+impl BatchOutcome {
+    /// Summarises a batch where only some of the files failed, and turns the failures into
+    /// the result of the run, which decides the exit code.
+    fn into_result(self) -> Result<()> {
+        if self.ok > 0 && !self.failed.is_empty() {
+            log::info!("{} file(s) succeeded, {} failed", self.ok, self.failed.len());
+        }
+        Error::error_or((), self.failed)
+    }
+
+    /// Adds up the outcomes of two batches.
+    fn merge(mut self, other: BatchOutcome) -> BatchOutcome {
+        self.ok += other.ok;
+        self.failed.extend(other.failed);
+        self.read += other.read;
+        self.written += other.written;
+        self
+    }
+}
+
+/// Runs `op` and retries it up to `--retries` times while it fails with a transient error,
+/// waiting twice as long before each further attempt.
+fn with_retries<T>(cli: &cli::Bzip2Cli, what: &str, mut op: impl FnMut() -> std::io::Result<T>) -> std::io::Result<T> {
+    let mut delay = std::time::Duration::from_millis(10);
+    let mut attempt = 0;
+    loop {
+        match op() {
+            Err(e) if attempt < cli.retries && is_transient(&e) => {
+                attempt += 1;
+                log::info!("{what}: {e}; retrying in {}ms ({attempt}/{})", delay.as_millis(), cli.retries);
+                std::thread::sleep(delay);
+                delay *= 2;
+            },
+            result => return result,
+        }
+    }
+}
+
+/// Tells whether `e` may go away when the operation is simply tried again.
+fn is_transient(e: &std::io::Error) -> bool {
+    matches!(e.kind(), std::io::ErrorKind::Interrupted | std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut)
+}
+
+fn decompress_each(file: &str, dest: &str, errs: &mut Vec<Error>, cli: &cli::Bzip2Cli, program_name: &str) {
+    match with_retries(cli, file, || std::fs::File::open(file)) {
+        Ok(input_file) => {
+            if cli.is_stdout(program_name) {
+                let result = decompress_output(input_file, StdoutWriter::new(cli), cli);
+                finish_each(file, None, result, errs, cli, program_name, "Decompressed");
+            } else {
+                let staging = staging_path(dest, cli);
+                match with_retries(cli, dest, || create_output(&staging, cli)) {
+                    Ok(output_file) => {
+                        let result = decompress_to_file(input_file, &output_file, cli)
+                            .inspect(|_| preserve_metadata(file, &output_file, dest, cli))
+                            .and_then(|stats| sync_output(&output_file, dest, Some(stats.written), cli).map(|_| stats))
+                            .and_then(|stats| move_into_place(file, &staging, dest, cli).map(|_| stats).map_err(Error::Io));
+                        // the partial output to remove is the temporary file.
+                        let partial = if result.is_err() { &staging } else { dest };
+                        finish_each(file, Some(partial), result, errs, cli, program_name, "Decompressed");
+                    }
+                    Err(e) => errs.push(Error::Io(e)),
+                }
+            }
+        },
+        Err(e) => errs.push(Error::Io(e)),
+    }
+}
+
+/// The writer of decompressed data to stdout. Stdout is line buffered, which would break
+/// binary data into many small writes, so the data is collected into `--buffer-size` instead;
+/// the decoders flush it at the end of each file, before its result is logged. With
+/// `--unbuffered`, each decoded chunk is written at once, for following the output.
+enum StdoutWriter {
+    Buffered(std::io::BufWriter<std::io::Stdout>),
+    Unbuffered(std::io::Stdout),
+}
+
+impl StdoutWriter {
+    fn new(cli: &cli::Bzip2Cli) -> Self {
+        if cli.unbuffered {
+            StdoutWriter::Unbuffered(std::io::stdout())
+        } else {
+            StdoutWriter::Buffered(std::io::BufWriter::with_capacity(cli.buffer_bytes(), std::io::stdout()))
+        }
+    }
+}
+
+impl Write for StdoutWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            StdoutWriter::Buffered(writer) => writer.write(buf),
+            StdoutWriter::Unbuffered(writer) => {
+                writer.write_all(buf)?;
+                writer.flush()?;
+                Ok(buf.len())
+            },
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            StdoutWriter::Buffered(writer) => writer.flush(),
+            StdoutWriter::Unbuffered(writer) => writer.flush(),
+        }
+    }
+}
+
+fn decompress_to_file(input_file: std::fs::File, output_file: &std::fs::File, cli: &cli::Bzip2Cli) -> Result<bzip2::Stats> {
+    #[cfg(unix)]
+    if cli.sparse {
+        return decompress_output(input_file, sparse::SparseWriter::new(output_file), cli);
+    }
+    decompress_output(input_file, output_file, cli)
+}
+
+/// Decompresses `input_file` into `writer`, checking the output against the CRC32 of
+/// `--checksum-trailer` when `input_file` has one.
+fn decompress_output(input_file: std::fs::File, writer: impl Write, cli: &cli::Bzip2Cli) -> Result<bzip2::Stats> {
+    let Some(expected) = stored_checksum(&input_file) else {
+        return decompress_stripped(input_file, writer, cli);
+    };
+    let mut digest = bzip2::DigestWriter::new(writer, Some(cli::Digest::Crc32));
+    let stats = decompress_stripped(input_file, &mut digest, cli)?;
+    match digest.finish() {
+        (_, Some(crc)) if crc == format!("{expected:08x}") => Ok(stats),
+        (_, crc) => Err(Error::CorruptData(format!("checksum mismatch: stored {expected:08x}, computed {}", crc.unwrap_or_default()))),
+    }
+}
+
+/// Returns the CRC32 recorded by `--checksum-trailer` at the end of `file`, leaving `file`
+/// at its start. Files that cannot seek, such as FIFOs, are not checked.
+fn stored_checksum(mut file: &std::fs::File) -> Option<u32> {
+    if !file.metadata().is_ok_and(|metadata| metadata.is_file()) {
+        return None;
+    }
+    let checksum = trailer::read_checksum(file)
+        .and_then(|checksum| std::io::Seek::rewind(&mut file).map(|_| checksum));
+    checksum.unwrap_or_else(|e| {
+        log::warn!("bzip2: cannot read the checksum trailer: {e}");
+        None
+    })
+}
+
+/// Decompresses stdin into `writer` like an input file: stripping the prefix of `--prepend`,
+/// and checking the CRC32 of `--checksum-trailer` when stdin is redirected from a file.
+#[cfg(unix)]
+fn decompress_stdin(writer: impl Write, cli: &cli::Bzip2Cli) -> Result<bzip2::Stats> {
+    use std::os::fd::AsFd;
+    let stdin = std::io::stdin().as_fd().try_clone_to_owned().map_err(Error::Io)?;
+    decompress_output(std::fs::File::from(stdin), writer, cli)
+}
+
+#[cfg(not(unix))]
+fn decompress_stdin(writer: impl Write, cli: &cli::Bzip2Cli) -> Result<bzip2::Stats> {
+    decompress_stripped(std::io::stdin(), writer, cli)
+}
+
+/// Decompresses `input_file` into `writer`, stripping the prefix of `--prepend`.
+fn decompress_stripped(input_file: impl std::io::Read, writer: impl Write, cli: &cli::Bzip2Cli) -> Result<bzip2::Stats> {
+    let Some(path) = &cli.prepend else {
+        return bzip2::decompress(input_file, writer, cli);
+    };
+    let prefix = prepend::Prefix::load(path).map_err(Error::Io)?;
+    let mut strip = prefix.strip(writer);
+    let stats = bzip2::decompress(input_file, &mut strip, cli)?;
+    strip.finish().map_err(Error::Io)?;
+    Ok(bzip2::Stats { written: stats.written - prefix.len(), ..stats })
+}
+
+/// Returns the settings compressing `file` with the level picked by `--auto-level`, or `None`
+/// when it is not given or `file` is not a regular file.
+fn auto_level_cli(file: &str, input_file: &std::fs::File, cli: &cli::Bzip2Cli) -> Option<cli::Bzip2Cli> {
+    let metadata = input_file.metadata().ok().filter(|metadata| cli.auto_level && metadata.is_file())?;
+    let level = cli::Bzip2Cli::auto_level(metadata.len());
+    log::info!("{file}: using level {level} for {} bytes", metadata.len());
+    Some(cli::Bzip2Cli { level: Some(level), ..cli.clone() })
+}
+
+fn compress_input(input_file: std::fs::File, writer: impl Write + Send, cli: &cli::Bzip2Cli) -> Result<bzip2::Stats> {
+    #[cfg(unix)]
+    if cli.mmap {
+        // inputs that cannot be mapped are read as usual.
+        match mmap::map(&input_file) {
+            Ok(Some(map)) => return compress_reader(&map[..], writer, cli),
+            Ok(None) => log::debug!("not a mappable file, reading it instead"),
+            Err(e) => log::info!("failed to map the input, reading it instead: {e}"),
+        }
+    }
+    compress_reader(input_file, writer, cli)
+}
+
+/// Compresses `reader` into `writer`, followed by the trailer of `--checksum-trailer`.
+fn compress_reader(reader: impl std::io::Read, mut writer: impl Write + Send, cli: &cli::Bzip2Cli) -> Result<bzip2::Stats> {
+    if !cli.checksum_trailer {
+        return compress_prefixed(reader, writer, cli);
+    }
+    let mut reader = trailer::CrcReader::new(reader);
+    let stats = compress_prefixed(&mut reader, &mut writer, cli)?;
+    trailer::write_checksum(writer, reader.crc()).map_err(Error::Io)?;
+    Ok(stats)
+}
+
+/// Compresses `reader` into `writer`, after the prefix of `--prepend`.
+fn compress_prefixed(reader: impl std::io::Read, writer: impl Write + Send, cli: &cli::Bzip2Cli) -> Result<bzip2::Stats> {
+    let Some(path) = &cli.prepend else {
+        return bzip2::compress(reader, writer, &cli.into());
+    };
+    let prefix = prepend::Prefix::load(path).map_err(Error::Io)?;
+    let stats = bzip2::compress(prefix.prepend(reader), writer, &cli.into())?;
+    Ok(bzip2::Stats { read: stats.read - prefix.len(), ..stats })
+}
+
+/// Opens `dest` for `--append`, creating it when missing, and returns it with its original
+/// length.
+fn append_output(dest: &str, cli: &cli::Bzip2Cli) -> std::io::Result<(std::fs::File, Option<u64>)> {
+    prepare_output_dir(dest, cli)?;
+    let file = std::fs::OpenOptions::new().append(true).create(true).open(dest)?;
+    let len = file.metadata()?.len();
+    if len > 0 {
+        log::info!("{dest}: appending to the existing archive");
+    }
+    Ok((file, Some(len)))
+}
+
+/// Copies the metadata of `file` to its output `dest` like bzip2 does, except for what
+/// `--no-preserve` excludes: the access and modification times, the permissions, and on Unix
+/// the owner. Failing to copy it is only warned about; special files give nothing to copy.
+fn preserve_metadata(file: &str, output: &std::fs::File, dest: &str, cli: &cli::Bzip2Cli) {
+    if special_file_kind(file).is_some() || is_descriptor_path(file) {
+        return;
+    }
+    let metadata = match std::fs::metadata(file) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            log::warn!("{file}: cannot read metadata to preserve: {e}");
+            return;
+        },
+    };
+    if cli.preserves(cli::Preserve::Time) {
+        let times = metadata.modified().and_then(|modified| {
+            metadata.accessed().map(|accessed| std::fs::FileTimes::new().set_modified(modified).set_accessed(accessed))
+        });
+        if let Err(e) = times.and_then(|times| output.set_times(times)) {
+            log::warn!("{dest}: cannot preserve times: {e}");
+        }
+    }
+    if cli.preserves(cli::Preserve::Mode) && let Err(e) = output.set_permissions(metadata.permissions()) {
+        log::warn!("{dest}: cannot preserve permissions: {e}");
+    }
+    // only root may give files away, so the owner is left alone for anyone else.
+    #[cfg(unix)]
+    if cli.preserves(cli::Preserve::Owner) && is_root() {
+        use std::os::unix::fs::MetadataExt;
+        if let Err(e) = std::os::unix::fs::fchown(output, Some(metadata.uid()), Some(metadata.gid())) {
+            log::warn!("{dest}: cannot preserve owner: {e}");
+        }
+    }
+}
+
+/// Tells whether the process runs with the effective user id of root.
+#[cfg(unix)]
+fn is_root() -> bool {
+    // SAFETY: geteuid has no preconditions and cannot fail.
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Creates the output file `dest`. With `--force`, a read-only `dest` is replaced as well.
+fn create_output(dest: &str, cli: &cli::Bzip2Cli) -> std::io::Result<std::fs::File> {
+    prepare_output_dir(dest, cli)?;
+    match std::fs::File::create(dest) {
+        Err(e) if cli.force && e.kind() == std::io::ErrorKind::PermissionDenied && std::path::Path::new(dest).exists() => {
+            log::info!("{dest}: replacing read-only output file");
+            make_replaceable(dest)?;
+            std::fs::File::create(dest)
+        },
+        result => result,
+    }
+}
+
+/// Returns the file the output `dest` is written to: a temporary file in the directory of
+/// `--temp-dir`, which [`move_into_place`] moves to `dest` once complete, or `dest` itself.
+/// With `--ratio-threshold` the temporary file is next to `dest`, so that an output which is
+/// not kept never replaces an existing one.
+fn staging_path(dest: &str, cli: &cli::Bzip2Cli) -> String {
+    let path = std::path::Path::new(dest);
+    let next_to_dest = || cli.ratio_threshold.and(path.parent()).map(std::path::Path::to_path_buf);
+    match (cli.temp_dir().or_else(next_to_dest), path.file_name()) {
+        (Some(dir), Some(name)) => {
+            let name = format!("{}.{}.part", name.to_string_lossy(), std::process::id());
+            dir.join(name).to_string_lossy().into_owned()
+        },
+        _ => dest.to_string(),
+    }
+}
+
+/// Moves the complete output of `file` from `staging` to `dest`. A rename cannot cross file
+/// systems, so the output is copied there instead, copying the metadata of `file` again.
+fn move_into_place(file: &str, staging: &str, dest: &str, cli: &cli::Bzip2Cli) -> std::io::Result<()> {
+    if staging == dest {
+        return Ok(());
+    }
+    prepare_output_dir(dest, cli)?;
+    match std::fs::rename(staging, dest) {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => copy_into_place(file, staging, dest, cli),
+        result => result,
+    }
+}
+
+fn copy_into_place(file: &str, staging: &str, dest: &str, cli: &cli::Bzip2Cli) -> std::io::Result<()> {
+    log::debug!("{staging}: copying to {dest} on another file system");
+    let mut output = create_output(dest, cli)?;
+    let copied = std::io::copy(&mut std::fs::File::open(staging)?, &mut output)
+        .and_then(|_| if cli.sync { output.sync_all() } else { Ok(()) });
+    if let Err(e) = copied {
+        let _ = std::fs::remove_file(dest);
+        return Err(e);
+    }
+    preserve_metadata(file, &output, dest, cli);
+    std::fs::remove_file(staging)
+}
+
+/// Creates the missing directory of the output `dest` with `--make-dirs`, or otherwise fails
+/// with a clearer message than the `NotFound` of creating the file in it.
+fn prepare_output_dir(dest: &str, cli: &cli::Bzip2Cli) -> std::io::Result<()> {
+    let dir = match std::path::Path::new(dest).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() && !dir.exists() => dir,
+        _ => return Ok(()),
+    };
+    if cli.make_dirs {
+        log::info!("{}: creating output directory", dir.display());
+        std::fs::create_dir_all(dir)
+    } else {
+        Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("{}: output directory does not exist (--make-dirs creates it)", dir.display())))
+    }
+}
+
+#[cfg(unix)]
+fn make_replaceable(dest: &str) -> std::io::Result<()> {
+    // unlinking only requires write permission on the directory.
+    std::fs::remove_file(dest)
+}
+
+#[cfg(not(unix))]
+fn make_replaceable(dest: &str) -> std::io::Result<()> {
+    let mut permissions = std::fs::metadata(dest)?.permissions();
+    permissions.set_readonly(false);
+    std::fs::set_permissions(dest, permissions)
+}
+
+/// An output whose contents can be forced to stable storage.
+trait Durable {
+    fn sync_all(&self) -> std::io::Result<()>;
+    fn len(&self) -> std::io::Result<u64>;
+}
+
+impl Durable for std::fs::File {
+    fn sync_all(&self) -> std::io::Result<()> {
+        std::fs::File::sync_all(self)
+    }
+
+    fn len(&self) -> std::io::Result<u64> {
+        self.metadata().map(|m| m.len())
+    }
+}
+
+/// Flushes `output` to disk (unless `--sync=false`) and verifies its size, so that the original
+/// file is only deleted once the output is safely stored. `expected` is the exact size to check
+/// for; when it is unknown the output only has to be non-empty.
+fn sync_output(output: &impl Durable, dest: &str, expected: Option<u64>, cli: &cli::Bzip2Cli) -> Result<()> {
+    if cli.sync {
+        output.sync_all().map_err(Error::Io)?;
+        log::debug!("{dest}: Synced output to disk");
+    }
+    let len = output.len().map_err(Error::Io)?;
+    let valid = match expected {
+        Some(expected) => len == expected,
+        None => len > 0,
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::InvalidInput(format!("bzip2: {dest}: unexpected output size ({len} bytes) after writing.")))
+    }
+}
+
+/// Completes processing of `file` once its output has been written to `dest` (`None` for stdout).
+/// On success the original file is deleted if [`cli::Bzip2Cli::should_delete`] says so; on failure the partial
+/// output is removed and the original is always kept.
+fn finish_each(file: &str, dest: Option<&str>, result: Result<bzip2::Stats>, errs: &mut Vec<Error>, cli: &cli::Bzip2Cli, program_name: &str, action: &str) {
+    match result {
+        Ok(stats) => {
+            log::info!("{file}: {action} to {} ({} -> {} bytes)", dest.unwrap_or("stdout"), stats.read, stats.written);
+            // special files are only read with --force, never removed.
+            if cli.should_delete(program_name) && special_file_kind(file).is_none() && !is_descriptor_path(file) {
+                match std::fs::remove_file(file) {
+                    Ok(_) => log::info!("{file}: Deleted original file"),
+                    Err(e) => errs.push(Error::Io(e)),
+                }
+            }
+        },
+        Err(e) => {
+            if let Some(dest) = dest {
+                discard_partial_output(dest, cli);
+            }
+            log::info!("{file}: Kept original file");
+            if e.is_no_space() {
+                errs.push(Error::NoSpaceLeft(dest.unwrap_or("stdout").to_string()));
+            } else {
+                errs.push(e);
+            }
+        },
+    }
+}
+
+/// Removes the partial output `dest` of a failed run, or renames it to `dest.broken` with
+/// `--keep-broken`.
+fn discard_partial_output(dest: &str, cli: &cli::Bzip2Cli) {
+    if cli.keep_broken {
+        let broken = format!("{dest}.broken");
+        match std::fs::rename(dest, &broken) {
+            Ok(_) => log::warn!("{dest}: partial output kept as {broken}"),
+            Err(e) => log::warn!("{dest}: failed to keep partial output as {broken}: {e}"),
+        }
+    } else {
+        match std::fs::remove_file(dest) {
+            Ok(_) => log::info!("{dest}: Removed partial output"),
+            Err(e) => log::warn!("{dest}: failed to remove partial output: {e}"),
+        }
+    }
+}
+
+/// Returns what kind of special file `file` is, or `None` for regular files (and anything
+/// that cannot be inspected, which fails later when it is opened).
+fn special_file_kind(file: &str) -> Option<&'static str> {
+    let file_type = std::fs::metadata(file).ok()?.file_type();
+    if file_type.is_dir() {
+        return Some("a directory");
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        if file_type.is_fifo() {
+            return Some("a FIFO");
+        } else if file_type.is_char_device() || file_type.is_block_device() {
+            return Some("a device");
+        } else if file_type.is_socket() {
+            return Some("a socket");
+        }
+    }
+    None
+}
+
+/// Tells whether `file` names an inherited descriptor, such as the `/dev/fd/63` of process
+/// substitution, rather than a file of its own. Such inputs are kept and their metadata is
+/// not copied, whatever the descriptor refers to.
+fn is_descriptor_path(file: &str) -> bool {
+    file == "/dev/stdin" || ["/dev/fd/", "/proc/self/fd/"].iter().any(|dir| file.starts_with(dir))
+}
+
+/// Tells whether `file` should be skipped because it is not a regular file and `--force` is
+/// not given. FIFOs, such as those of process substitution, are read like regular files;
+/// like any special file, they are kept afterwards.
+fn skip_special_file(file: &str, cli: &cli::Bzip2Cli) -> bool {
+    match special_file_kind(file) {
+        Some("a FIFO") => {
+            log::info!("{file}: reading from a FIFO, which is kept");
+            false
+        },
+        Some(kind) if !cli.force => {
+            log::warn!("bzip2: Input file {file} is {kind}, skipping.");
+            true
+        },
+        _ => false,
+    }
+}
+
+/// Tells whether `dest` is `file` itself, reporting it to `errs`, as writing it would truncate
+/// the input before it is read.
+fn is_same_file(file: &str, dest: &str, errs: &mut Vec<Error>) -> bool {
+    let same = is_same_identity(file, dest);
+    if same {
+        errs.push(Error::InvalidInput(format!("bzip2: {file}: input and output are the same file")));
+    }
+    same
+}
+
+/// Tells whether the existing paths `file` and `dest` lead to the same file. On Unix, that is
+/// the same inode on the same device, whatever symlinks or hard links lead there.
+#[cfg(unix)]
+fn is_same_identity(file: &str, dest: &str) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (std::fs::metadata(file), std::fs::metadata(dest)) {
+        (Ok(file), Ok(dest)) => (file.dev(), file.ino()) == (dest.dev(), dest.ino()),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_same_identity(file: &str, dest: &str) -> bool {
+    match (std::fs::canonicalize(file), std::fs::canonicalize(dest)) {
+        (Ok(file), Ok(dest)) => file == dest,
+        _ => false,
+    }
+}
+
+/// Decides whether `dest` may be written. An existing file is only overwritten with `--force`,
+/// or after the user confirms it when running interactively (or with `--interactive`).
+fn can_overwrite(dest: &str, cli: &cli::Bzip2Cli, errs: &mut Vec<Error>) -> bool {
+    can_overwrite_with(dest, cli, errs, &mut std::io::stdin().lock(), &mut std::io::stderr())
+}
+
+fn can_overwrite_with(dest: &str, cli: &cli::Bzip2Cli, errs: &mut Vec<Error>, input: &mut impl BufRead, prompt: &mut impl Write) -> bool {
+    if cli.force || !std::path::Path::new(dest).exists() {
+        return true;
+    }
+    if cli.interactive || (!cli.is_quiet() && std::io::stdin().is_terminal() && std::io::stderr().is_terminal()) {
+        let _ = write!(prompt, "bzip2: overwrite {dest}? (y/N) ");
+        let _ = prompt.flush();
+        let mut answer = String::new();
+        let confirmed = input.read_line(&mut answer).is_ok()
+            && matches!(answer.trim(), "y" | "Y" | "yes");
+        if !confirmed {
+            log::info!("{dest}: not overwritten");
+        }
+        confirmed
+    } else {
+        errs.push(Error::FileExists(dest.to_string()));
+        false
+    }
+}
+
+/// Creates the `--output-dir` directory when output files are going to be written into it.
+fn create_output_dir(cli: &cli::Bzip2Cli, to_stdout: bool) -> Result<()> {
+    match &cli.output_dir {
+        Some(dir) if !to_stdout && !cli.is_empty() => std::fs::create_dir_all(dir).map_err(Error::Io),
+        _ => Ok(()),
+    }
+}
+
+/// Returns the line logged before processing the input files, such as `queued 3 files to compress`,
+/// so that an interrupted run can be compared against the `Finished` markers.
+fn queued_line(cli: &cli::Bzip2Cli, action: &str) -> String {
+    match cli.input_files.len() {
+        1 => format!("queued 1 file to {action}"),
+        count => format!("queued {count} files to {action}"),
+    }
+}
+
+/// Returns the position of the input file at `index` among all of them, such as `2/3`.
+fn position(index: usize, cli: &cli::Bzip2Cli) -> String {
+    format!("{}/{}", index + 1, cli.input_files.len())
+}
+
+fn perform_decompress(cli: &cli::Bzip2Cli, program_name: &str, timings: &mut Vec<Timing>) -> Result<BatchOutcome> {
+    log::info!("Decompressing files...");
+    create_output_dir(cli, cli.is_stdout(program_name))?;
+    let mut errs = vec![];
+    let mut ok = 0;
+    if !cli.is_empty() {
+        log::info!("{}", queued_line(cli, "decompress"));
+    }
+    for (index, file) in cli.iter().enumerate() {
+        if skip_special_file(file, cli) {
+            continue;
+        }
+        log::info!("{file}: Decompressing file ({})", position(index, cli));
+        let dest = match (cli.output_template.as_deref(), original_name(file, cli)) {
+            (Some(output_template), _) => template::render(output_template, file),
+            (None, Some(dest)) => cli.output_path(&dest),
+            // the name only matters when it is derived from the input.
+            (None, None) if cli.strict_suffix && !cli.is_stdout(program_name) && cli.output.is_none() => {
+                errs.push(Error::InvalidInput(format!("bzip2: Can't guess original name for {file}, and --strict-suffix does not allow {file}.out")));
+                continue;
+            },
+            (None, None) => {
+                match unknown_name_warning(file, cli) {
+                    Some(warning) => log::warn!("{warning}"),
+                    None => log::info!("{file}: decompressing to {file}.out"),
+                }
+                cli.output_path(&format!("{file}.out"))
+            },
+        };
+        if !cli.is_stdout(program_name) && (is_same_file(file, &dest, &mut errs) || !can_overwrite(&dest, cli, &mut errs)) {
+            continue;
+        }
+        let failures = errs.len();
+        timed(file, timings, || decompress_each(file, &dest, &mut errs, cli, program_name));
+        if errs.len() == failures {
+            ok += 1;
+        }
+        log::info!("{file}: Finished ({})", position(index, cli));
+    }
+    if cli.is_empty() {
+        if cli.is_stdout(program_name) {
+            match decompress_stdin(StdoutWriter::new(cli), cli) {
+                Ok(stats) => {
+                    log::info!("stdin: Decompressed to stdout ({} -> {} bytes)", stats.read, stats.written);
+                    ok += 1;
+                },
+                Err(e) => errs.push(e),
+            }
+        } else {
+            errs.push(Error::CannotWriteToStdout())
+        }
+    }
+    Ok(BatchOutcome { ok, failed: errs, ..BatchOutcome::default() })
+}
+
+/// Returns the name `file` is decompressed to: the name stored by `--name` when `-N` is given
+/// and `file` has one, otherwise `file` without its compressed suffix.
+fn original_name(file: &str, cli: &cli::Bzip2Cli) -> Option<String> {
+    let stored = if cli.name {
+        match std::fs::File::open(file).and_then(trailer::read_name) {
+            Ok(name) => name,
+            Err(e) => {
+                log::warn!("bzip2: {file}: cannot read the stored name: {e}");
+                None
+            },
+        }
+    } else {
+        None
+    };
+    // only the last component is used, so that a stored name cannot escape the directory of `file`.
+    match stored.as_deref().map(std::path::Path::new).and_then(std::path::Path::file_name) {
+        Some(name) => {
+            log::info!("{file}: using the stored name {}", name.to_string_lossy());
+            Some(std::path::Path::new(file).with_file_name(name).to_string_lossy().into_owned())
+        },
+        None => suffix::original_name(file, &cli.custom_suffixes()),
+    }
+}
+
+/// Returns the warning about decompressing `file`, whose original name cannot be guessed, to
+/// `file.out`. This is noncritical like in bzip2: the run still succeeds, and `-q` silences the
+/// warning. With `--force` the fallback is accepted and not warned about.
+fn unknown_name_warning(file: &str, cli: &cli::Bzip2Cli) -> Option<String> {
+    (!cli.force).then(|| format!("bzip2: Can't guess original name for {file} -- using {file}.out instead"))
+}
+
+/// Appends the `--name` trailer recording the name of `file` to `writer`.
+fn store_name(file: &str, writer: impl Write, cli: &cli::Bzip2Cli) -> Result<()> {
+    match std::path::Path::new(file).file_name() {
+        Some(name) if cli.name => trailer::write_name(writer, &name.to_string_lossy()).map_err(Error::Io),
+        _ => Ok(()),
+    }
+}
+
+/// Compresses `file` into `dest`, returning its stats when the output is kept.
+fn compress(file: &str, dest: &str, errs: &mut Vec<Error>, cli: &cli::Bzip2Cli, program_name: &str) -> Option<bzip2::Stats> {
+    match with_retries(cli, file, || std::fs::File::open(file)) {
+        Ok(input_file) => {
+            let auto_cli = auto_level_cli(file, &input_file, cli);
+            let cli = auto_cli.as_ref().unwrap_or(cli);
+            // an archive is appended to in place.
+            let staging = if cli.append { dest.to_string() } else { staging_path(dest, cli) };
+            let mut rejected = None;
+            let (dest, result) = if cli.is_stdout(program_name) {
+                (None, compress_input(input_file, std::io::stdout(), cli)
+                    .and_then(|stats| store_name(file, std::io::stdout(), cli).map(|_| stats)))
+            } else {
+                let output = with_retries(cli, dest, || if cli.append {
+                    append_output(dest, cli)
+                } else {
+                    create_output(&staging, cli).map(|file| (file, None))
+                });
+                match output {
+                    Ok((output_file, appended_at)) => {
+                        let result = compress_input(input_file, &output_file, cli)
+                            .and_then(|stats| store_name(file, &output_file, cli).map(|_| stats))
+                            // an archive appended to holds several files, so it keeps its own metadata.
+                            .inspect(|_| if !cli.append {
+                                preserve_metadata(file, &output_file, dest, cli);
+                            })
+                            .and_then(|stats| sync_output(&output_file, dest, None, cli).map(|_| stats));
+                        rejected = result.as_ref().ok().and_then(|stats| ratio_above_threshold(stats, cli));
+                        let result = match rejected {
+                            Some(_) => result,
+                            None => result.and_then(|stats| move_into_place(file, &staging, dest, cli).map(|_| stats).map_err(Error::Io)),
+                        };
+                        match appended_at {
+                            // the archive appended to is restored rather than removed.
+                            Some(len) if result.is_err() || rejected.is_some() => {
+                                match output_file.set_len(len) {
+                                    Ok(_) if rejected.is_some() => log::info!("{dest}: Removed the stream of {file}"),
+                                    Ok(_) => log::info!("{dest}: Removed partial stream"),
+                                    Err(e) => log::warn!("{dest}: failed to remove partial stream: {e}"),
+                                }
+                                (None, result)
+                            },
+                            // an existing output stays as it was, as only the temporary file is removed.
+                            _ if rejected.is_some() => {
+                                if let Err(e) = std::fs::remove_file(&staging) {
+                                    errs.push(Error::Io(e));
+                                }
+                                (None, result)
+                            },
+                            // the partial output to remove is the temporary file.
+                            _ if result.is_err() => (Some(staging.as_str()), result),
+                            _ => (Some(dest), result),
+                        }
+                    }
+                    Err(e) => {
+                        errs.push(Error::Io(e));
+                        return None;
+                    }
+                }
+            };
+            match &result {
+                // an empty file always becomes the bare 14-byte stream, which is not worth a warning.
+                Ok(stats) if stats.read == 0 => log::info!("{file}: no data compressed."),
+                Ok(stats) => if let Some(warning) = expansion_warning(file, stats) {
+                    log::warn!("{warning}");
+                },
+                Err(_) => {},
+            }
+            if let Some(ratio) = rejected {
+                // the original stays as the file to use, whatever --keep says.
+                log::info!("{file}: compressed to {ratio:.3} of its size, above --ratio-threshold; kept uncompressed");
+                return None;
+            }
+            let stats = result.as_ref().ok().copied();
+            finish_each(file, dest, result, errs, cli, program_name, "Compressed");
+            stats
+        },
+        Err(e) => {
+            errs.push(Error::Io(e));
+            None
+        },
+    }
+}
+
+/// Returns the ratio of the compressed to the original size when it is above
+/// `--ratio-threshold`, meaning that the compressed file is not worth keeping.
+fn ratio_above_threshold(stats: &bzip2::Stats, cli: &cli::Bzip2Cli) -> Option<f64> {
+    let threshold = cli.ratio_threshold?;
+    let ratio = stats.written as f64 / stats.read as f64;
+    (stats.read > 0 && ratio > threshold).then_some(ratio)
+}
+
+/// Returns a warning message when compressing `file` did not make it smaller.
+fn expansion_warning(file: &str, stats: &bzip2::Stats) -> Option<String> {
+    if stats.written >= stats.read {
+        Some(format!("{file}: file did not compress ({} -> {} bytes)", stats.read, stats.written))
+    } else {
+        None
+    }
+}
+
+/// Estimates the time left to process `total` bytes when `done` bytes took `elapsed`,
+/// assuming the throughput so far.
+fn eta(done: u64, total: u64, elapsed: Duration) -> Option<Duration> {
+    if done == 0 || elapsed.is_zero() {
+        return None;
+    }
+    let remaining = total.saturating_sub(done) as f64;
+    Some(Duration::from_secs_f64(remaining * elapsed.as_secs_f64() / done as f64))
+}
+
+/// Shows the progress of compressing several files on a terminal, with the estimated time left
+/// for the whole batch after each file.
+struct BatchProgress {
+    enabled: bool,
+    files: usize,
+    done_files: usize,
+    total: u64,
+    done: u64,
+    start: Instant,
+}
+
+impl BatchProgress {
+    fn new(cli: &cli::Bzip2Cli, program_name: &str) -> Self {
+        Self {
+            enabled: cli.input_files.len() > 1 && !cli.is_quiet() && !cli.is_stdout(program_name) && std::io::stderr().is_terminal(),
+            files: cli.input_files.len(),
+            done_files: 0,
+            total: cli.iter().map(|file| file_size(file)).sum(),
+            done: 0,
+            start: Instant::now(),
+        }
+    }
+
+    /// Returns the progress line, such as `3/10 files, 42% done, about 12s left`.
+    fn line(&self, elapsed: Duration) -> String {
+        let percent = (self.done * 100).checked_div(self.total).unwrap_or(100);
+        let left = match eta(self.done, self.total, elapsed) {
+            Some(left) => format!("about {}s left", left.as_secs_f64().ceil()),
+            None => "estimating time left".to_string(),
+        };
+        format!("{}/{} files, {percent}% done, {left}", self.done_files, self.files)
+    }
+
+    /// Counts a file of `size` bytes as done and updates the progress line.
+    fn file_done(&mut self, size: u64) {
+        self.done_files += 1;
+        self.done += size;
+        if self.enabled {
+            eprint!("\r\x1b[K{}", self.line(self.start.elapsed()));
+        }
+    }
+
+    fn finish(&self) {
+        if self.enabled && self.done_files > 0 {
+            eprintln!();
+        }
+    }
+}
+
+fn perform_compress(cli: &cli::Bzip2Cli, program_name: &str, timings: &mut Vec<Timing>) -> Result<BatchOutcome> {
+    log::info!("Compressing files...");
+    let (compress_kb, decompress_kb) = bzip2::estimate_memory(cli.compress_level(), cli.small);
+    log::info!("estimated memory use at -{}: {compress_kb}k to compress, {decompress_kb}k to decompress", cli.compress_level());
+    let to_stdout = cli.is_stdout(program_name);
+    if to_stdout && !cli.force && std::io::stdout().is_terminal() {
+        return Err(Error::CannotWriteToStdout());
+    }
+    create_output_dir(cli, to_stdout)?;
+    let mut errs = vec![];
+    let mut ok = 0;
+    let (mut read, mut written) = (0, 0);
+    let mut batch = BatchProgress::new(cli, program_name);
+    if !cli.is_empty() {
+        log::info!("{}", queued_line(cli, "compress"));
+    }
+    for (index, file) in cli.iter().enumerate() {
+        let size = file_size(file);
+        if let Some(suffix) = suffix::compressed_suffix(file, &cli.custom_suffixes()) {
+            // skipped with a warning like bzip2, so that compressing a glob is not aborted by it.
+            log::warn!("{}", Error::AlreadyCompressed(file.clone(), suffix.to_string()));
+            batch.file_done(size);
+            continue;
+        }
+        if skip_special_file(file, cli) {
+            batch.file_done(size);
+            continue;
+        }
+        if cli.skip_compressed && let Some(format) = suffix::compressed_format(file) {
+            log::warn!("bzip2: Input file {file} is already {format} data, skipping.");
+            batch.file_done(size);
+            continue;
+        }
+        log::info!("{file}: Compressing file ({})", position(index, cli));
+        let dest = match cli.output_template.as_deref() {
+            Some(output_template) => template::render(output_template, file),
+            None => cli.output_path(&format!("{file}{}", cli.compressed_suffix())),
+        };
+        if !to_stdout && (is_same_file(file, &dest, &mut errs) || !cli.append && !can_overwrite(&dest, cli, &mut errs)) {
+            batch.file_done(size);
+            continue;
+        }
+        let failures = errs.len();
+        if let Some(stats) = timed(file, timings, || compress(file, &dest, &mut errs, cli, program_name)) {
+            read += stats.read;
+            written += stats.written;
+        }
+        if errs.len() == failures {
+            ok += 1;
+        }
+        log::info!("{file}: Finished ({})", position(index, cli));
+        batch.file_done(size);
+    }
+    batch.finish();
+    if cli.is_empty() {
+        if to_stdout {
+            match compress_reader(std::io::stdin(), std::io::stdout(), cli) {
+                Ok(stats) => {
+                    log::info!("stdin: Compressed to stdout ({} -> {} bytes)", stats.read, stats.written);
+                    ok += 1;
+                },
+                Err(e) => errs.push(e),
+            }
+        } else {
+            errs.push(Error::CannotWriteToStdout())
+        }
+    }
+    if cli.input_files.len() > 1 {
+        log::info!("{}", total_line(read, written));
+    }
+    Ok(BatchOutcome { ok, failed: errs, read, written })
+}
+
+/// Returns the line summing up the compression of a batch, such as
+/// `Total: 120.0 MiB → 38.0 MiB (68.3% saved)`.
+fn total_line(read: u64, written: u64) -> String {
+    let saved = if read == 0 { 0.0 } else { 100.0 * (1.0 - written as f64 / read as f64) };
+    format!("Total: {} → {} ({saved:.1}% saved)", format_size(read), format_size(written))
+}
+
+/// Formats `bytes` in binary units, such as `38.2 MiB`.
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+/// Decompresses the input files with a compressed suffix and compresses the others, for
+/// `--auto`. Each group runs as its own batch, so that no file falls back to stdin.
+fn perform_auto(cli: &cli::Bzip2Cli, program_name: &str, timings: &mut Vec<Timing>) -> Result<BatchOutcome> {
+    let (compressed, plain): (Vec<String>, Vec<String>) = cli.iter().cloned()
+        .partition(|file| suffix::compressed_suffix(file, &cli.custom_suffixes()).is_some());
+    let mut outcome = BatchOutcome::default();
+    if !compressed.is_empty() {
+        let cli = cli::Bzip2Cli { input_files: compressed, ..cli.clone() };
+        outcome = outcome.merge(perform_decompress(&cli, program_name, timings)?);
+    }
+    if !plain.is_empty() {
+        let cli = cli::Bzip2Cli { input_files: plain, ..cli.clone() };
+        outcome = outcome.merge(perform_compress(&cli, program_name, timings)?);
+    }
+    Ok(outcome)
+}
+
+/// The name, processing time and size of an input file, as reported by `--time`.
+type Timing = (String, Duration, u64);
+
+/// Returns the size of `file`, or zero if it cannot be inspected.
+fn file_size(file: &str) -> u64 {
+    std::fs::metadata(file).map(|m| m.len()).unwrap_or(0)
+}
+
+/// Runs `operation` on `file`, recording how long it took. The size is taken beforehand,
+/// since the input may be removed afterwards.
+fn timed<T>(file: &str, timings: &mut Vec<Timing>, operation: impl FnOnce() -> T) -> T {
+    let size = file_size(file);
+    let start = Instant::now();
+    let result = operation();
+    timings.push((file.to_string(), start.elapsed(), size));
+    result
+}
+
+/// Formats the `--time` report as a table aligned on the longest file name.
+fn format_timings(timings: &[Timing]) -> String {
+    let width = timings.iter().map(|(file, _, _)| file.len()).max().unwrap_or(0).max("file".len());
+    let mut table = format!("{:<width$} {:>12} {:>10}\n", "file", "bytes", "seconds");
+    for (file, elapsed, size) in timings {
+        table.push_str(&format!("{file:<width$} {size:>12} {:>10.3}\n", elapsed.as_secs_f64()));
+    }
+    table
+}
+
+/// Formats the `--benchmark` results of `file`, which is `size` bytes, as a table.
+#[cfg(feature = "both")]
+fn format_benchmark(file: &str, size: u64, results: &[bzip2::Benchmark]) -> String {
+    let mut table = format!("{file}: {size} bytes\n{:<10} {:>12} {:>8} {:>10} {:>12}\n", "backend", "compressed", "ratio", "seconds", "MB/s");
+    for result in results {
+        let info = bzip2::StreamInfo { compressed: result.compressed.len() as u64, uncompressed: size, streams: 1, block_size: None };
+        let seconds = result.elapsed.as_secs_f64();
+        let throughput = if seconds > 0.0 { size as f64 / seconds / 1_000_000.0 } else { 0.0 };
+        table.push_str(&format!("{:<10} {:>12} {:>7.2}% {seconds:>10.3} {throughput:>12.2}\n", result.backend, info.compressed, info.ratio()));
+    }
+    table
+}
+
+/// Returns `len` incompressible bytes from a splitmix64 generator, the same on every run.
+fn pseudo_random_bytes(len: usize) -> Vec<u8> {
+    let mut state = 0u64;
+    (0..len).map(|_| {
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        ((z ^ (z >> 31)) >> 56) as u8
+    }).collect()
+}
+
+/// The data `--self-test` round-trips: empty, all zeros over several blocks, incompressible
+/// and repetitive.
+fn self_test_vectors() -> Vec<(&'static str, Vec<u8>, CompressionOptions)> {
+    vec![
+        ("empty", vec![], CompressionOptions::default()),
+        ("zeros", vec![0; 250_000], CompressionOptions::default().level(1)),
+        ("random", pseudo_random_bytes(64 * 1024), CompressionOptions::default()),
+        ("repetitive", b"abcabcabd".repeat(20_000), CompressionOptions::default().level(9)),
+    ]
+}
+
+/// Compresses and decompresses each of the built-in vectors for `--self-test`, writing a line
+/// of `PASS` or `FAIL`, a tab and the name of each to `out`.
+fn self_test(out: &mut impl Write) -> Result<()> {
+    let mut failed = 0;
+    for (name, data, options) in self_test_vectors() {
+        let result = compress_to_vec(&data, options)
+            .and_then(|compressed| decompress_to_vec(&compressed));
+        let line = match result {
+            Ok(decompressed) if decompressed == data => format!("PASS\t{name} ({} bytes)", data.len()),
+            Ok(_) => format!("FAIL\t{name}: the decompressed data differs"),
+            Err(e) => format!("FAIL\t{name}: {e}"),
+        };
+        failed += usize::from(line.starts_with("FAIL"));
+        writeln!(out, "{line}").map_err(Error::Io)?;
+    }
+    match failed {
+        0 => Ok(()),
+        _ => Err(Error::InvalidInput(format!("bzip2: self-test failed for {failed} vector(s) with {}", bzip2::backend()))),
+    }
+}
+
+/// Compresses each file with both backends and prints how they compare, without writing
+/// any file.
+fn perform_benchmark(cli: &cli::Bzip2Cli) -> Result<()> {
+    #[cfg(feature = "both")]
+    {
+        let mut errs = vec![];
+        for file in cli.iter() {
+            log::info!("{file}: Benchmarking file");
+            let result = std::fs::read(file).map_err(Error::Io)
+                .and_then(|data| bzip2::benchmark(&data, cli).map(|results| format_benchmark(file, data.len() as u64, &results)));
+            match result {
+                Ok(table) => print!("{table}"),
+                Err(e) => errs.push(e),
+            }
+        }
+        Error::error_or((), errs)
+    }
+    #[cfg(not(feature = "both"))]
+    {
+        let _ = cli;
+        Err(Error::InvalidInput("bzip2: --benchmark needs bzip2rs built with the `both` feature".to_string()))
+    }
+}
+
+/// The outcome of testing a file: the size of its decompressed data and its `--digest`.
+struct Tested {
+    bytes: u64,
+    digest: Option<String>,
+}
+
+fn test_each(file: &str, cli: &cli::Bzip2Cli) -> Result<Tested> {
+    log::info!("{file}: Testing file");
+    let input = std::fs::File::open(file).map_err(Error::Io)?;
+    match suffix::original_name(file, &cli.custom_suffixes()) {
+        Some(original) if cli.test >= 2 && std::path::Path::new(&original).is_file() => {
+            log::info!("{file}: Comparing with {original}");
+            compare_each(input, &original, cli)
+        },
+        _ if cli.continue_on_error => test_blocks(file, input, cli),
+        _ if cli.digest.is_some() || stored_checksum(&input).is_some() => decompress_tested(input, std::io::sink(), cli).map(|(tested, _)| tested),
+        _ => bzip2::test_integrity(input, cli).map(|bytes| Tested { bytes, digest: None }),
+    }
+}
+
+/// Tests each block of `input` for `--continue`, failing with the number of bad blocks.
+fn test_blocks(file: &str, input: std::fs::File, cli: &cli::Bzip2Cli) -> Result<Tested> {
+    let tally = bzip2::check_blocks(input, cli).map_err(Error::Io)?;
+    log::info!("{file}: {} of {} block(s) bad", tally.bad, tally.blocks);
+    match tally {
+        bzip2::BlockTally { blocks: 0, .. } => Err(Error::CorruptData(format!("{file}: no blocks found"))),
+        bzip2::BlockTally { bad: 0, bytes, .. } => Ok(Tested { bytes, digest: None }),
+        bzip2::BlockTally { blocks, bad, .. } => Err(Error::CorruptData(format!("{file}: {bad} of {blocks} block(s) bad"))),
+    }
+}
+
+/// Decompresses `input` into `writer`, computing the `--digest` on the way.
+fn decompress_tested<W: Write>(input: std::fs::File, writer: W, cli: &cli::Bzip2Cli) -> Result<(Tested, W)> {
+    let mut digest = bzip2::DigestWriter::new(writer, cli.digest);
+    let stats = decompress_output(input, &mut digest, cli)?;
+    let (writer, digest) = digest.finish();
+    Ok((Tested { bytes: stats.written, digest }, writer))
+}
+
+/// Decompresses `input` and checks that the result is the same as the contents of `original`.
+fn compare_each(input: std::fs::File, original: &str, cli: &cli::Bzip2Cli) -> Result<Tested> {
+    let expected = std::fs::File::open(original).map_err(Error::Io)?;
+    let comparing = bzip2::ComparingWriter::new(std::io::BufReader::new(expected), original);
+    let (tested, comparing) = decompress_tested(input, comparing, cli)?;
+    comparing.finish().map_err(Error::Io)?;
+    Ok(tested)
+}
+
+/// Decompresses the first input file and compares the result with the second for `--compare`.
+fn perform_compare(cli: &cli::Bzip2Cli) -> Result<()> {
+    let [compressed, original] = cli.input_files.as_slice() else {
+        return Err(Error::InvalidInput("bzip2: --compare takes a compressed file and an uncompressed file".to_string()));
+    };
+    log::info!("{compressed}: Comparing with {original}");
+    let input = std::fs::File::open(compressed).map_err(Error::Io)?;
+    let tested = compare_each(input, original, cli)?;
+    log::info!("{compressed}: same as {original} ({} bytes)", tested.bytes);
+    Ok(())
+}
+
+/// Tests `file`, measuring how long it took.
+fn test_timed(file: &str, cli: &cli::Bzip2Cli) -> (Result<Tested>, Duration) {
+    let start = Instant::now();
+    (test_each(file, cli), start.elapsed())
+}
+
+/// Tests the integrity of all input files using up to `--threads` worker threads.
+/// The results are returned in the order of the input files.
+fn test_files(cli: &cli::Bzip2Cli) -> Vec<(Result<Tested>, Duration)> {
+    let files = cli.input_files.as_slice();
+    let threads = cli.threads.clamp(1, files.len().max(1));
+    if threads == 1 {
+        return files.iter().map(|file| test_timed(file, cli)).collect();
+    }
+    let next = AtomicUsize::new(0);
+    let mut results = std::thread::scope(|scope| {
+        let workers = (0..threads).map(|_| scope.spawn(|| {
+            let mut results = vec![];
+            loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                match files.get(index) {
+                    Some(file) => results.push((index, test_timed(file, cli))),
+                    None => break results,
+                }
+            }
+        })).collect::<Vec<_>>();
+        workers.into_iter()
+            .flat_map(|worker| worker.join().expect("test worker panicked"))
+            .collect::<Vec<_>>()
+    });
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+fn perform_test(cli: &cli::Bzip2Cli, timings: &mut Vec<Timing>) -> Result<()> {
+    log::info!("Testing integrity of compressed files...");
+    let mut errs = vec![];
+    for (file, (result, elapsed)) in cli.iter().zip(test_files(cli)) {
+        timings.push((file.clone(), elapsed, file_size(file)));
+        if cli.stdout {
+            write_report(&mut std::io::stdout(), file, result.is_ok());
+        }
+        match result {
+            Ok(tested) => {
+                log::info!("{file}: OK ({} bytes)", tested.bytes);
+                if let Some(digest) = tested.digest {
+                    println!("{digest}  {file}");
+                }
+            },
+            Err(e) => {
+                log::info!("{file}: FAIL");
+                errs.push(e);
+            },
+        }
+    }
+    Error::error_or((), errs)
+}
+
+/// Writes the `-tc` report line of `file`: `OK` or `FAIL`, a tab and the file name.
+fn write_report(writer: &mut impl Write, file: &str, ok: bool) {
+    let status = if ok { "OK" } else { "FAIL" };
+    if let Err(e) = writeln!(writer, "{status}\t{file}") {
+        log::warn!("failed to write the report of {file}: {e}");
+    }
+}
+
+fn list_each(file: &str, cli: &cli::Bzip2Cli) -> Result<bzip2::StreamInfo> {
+    let compressed = std::fs::metadata(file).map_err(Error::Io)?.len();
+    let streams = bzip2::stream_count(std::fs::File::open(file).map_err(Error::Io)?)?;
+    let uncompressed = bzip2::test_integrity(std::fs::File::open(file).map_err(Error::Io)?, cli)?;
+    let block_size = bzip2::block_size(std::fs::File::open(file).map_err(Error::Io)?)?;
+    Ok(bzip2::StreamInfo { compressed, uncompressed, streams, block_size })
+}
+
+/// Formats the `--list` table of `entries`, followed by a `(totals)` row when more than one
+/// file is listed.
+fn format_list(entries: &[(String, bzip2::StreamInfo)]) -> String {
+    let row = |info: &bzip2::StreamInfo, name: &str| format!("{:>12} {:>12} {:>5.1}% {:>7}  {name}\n", info.compressed, info.uncompressed, info.ratio(), info.streams);
+    let mut table = format!("{:>12} {:>12} {:>6} {:>7}  name\n", "compressed", "uncompressed", "ratio", "streams");
+    let mut totals = bzip2::StreamInfo::default();
+    for (file, info) in entries {
+        table.push_str(&row(info, file));
+        totals.add(info);
+    }
+    if entries.len() > 1 {
+        table.push_str(&row(&totals, "(totals)"));
+    }
+    table
+}
+
+/// Formats the `--list --json` output of `entries`: an array with an object per file. With
+/// `--time`, `elapsed` holds how long each file took, given as its `seconds`.
+fn format_list_json(entries: &[(String, bzip2::StreamInfo)], elapsed: Option<&[Duration]>) -> String {
+    let objects = entries.iter().enumerate().map(|(index, (file, info))| {
+        let block_size = info.block_size.map_or("null".to_string(), |size| size.to_string());
+        let seconds = elapsed.and_then(|elapsed| elapsed.get(index))
+            .map_or(String::new(), |elapsed| format!(", \"seconds\": {:.3}", elapsed.as_secs_f64()));
+        format!("  {{\"name\": {}, \"compressed\": {}, \"uncompressed\": {}, \"ratio\": {:.1}, \"block_size\": {block_size}, \"streams\": {}{seconds}}}",
+            json_string(file), info.compressed, info.uncompressed, info.ratio(), info.streams)
+    }).collect::<Vec<_>>();
+    if objects.is_empty() {
+        "[]\n".to_string()
+    } else {
+        format!("[\n{}\n]\n", objects.join(",\n"))
+    }
+}
+
+/// Quotes `s` as a JSON string.
+fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn perform_list(cli: &cli::Bzip2Cli, timings: &mut Vec<Timing>) -> Result<()> {
+    log::info!("Listing compressed files...");
+    let mut errs = vec![];
+    let mut entries = vec![];
+    let mut elapsed = vec![];
+    for file in cli.iter() {
+        match timed(file, timings, || list_each(file, cli)) {
+            Ok(info) => {
+                entries.push((file.clone(), info));
+                elapsed.extend(timings.last().map(|(_, elapsed, _)| *elapsed));
+            },
+            Err(e) => {
+                log::warn!("{file}: skipped, not included in the totals");
+                errs.push(e);
+            },
+        }
+    }
+    if cli.json {
+        // the timings go into the records rather than a table on stderr.
+        print!("{}", format_list_json(&entries, cli.time.then_some(elapsed.as_slice())));
+    } else {
+        print!("{}", format_list(&entries));
+    }
+    Error::error_or((), errs)
+}
+
+/// Compresses `data` in memory with `options`, given as [`CompressionOptions`] or a
+/// [`cli::Bzip2Cli`].
+///
+/// ```
+/// use bzip2rs::cli::Bzip2Cli;
+///
+/// let data = b"hello, hello, hello, world";
+/// let compressed = bzip2rs::compress_to_vec(data, &Bzip2Cli::default()).unwrap();
+/// assert!(compressed.starts_with(b"BZh"));
+/// assert_eq!(data.to_vec(), bzip2rs::decompress_to_vec(&compressed).unwrap());
+/// ```
+pub fn compress_to_vec(data: &[u8], options: impl Into<CompressionOptions>) -> Result<Vec<u8>> {
+    let mut compressed = vec![];
+    bzip2::compress(data, &mut compressed, &options.into())?;
+    Ok(compressed)
+}
+
+/// Decompresses the bzip2 `data` in memory. Concatenated streams are decompressed one after another.
+///
+/// ```
+/// use bzip2rs::cli::Bzip2Cli;
+///
+/// let compressed = bzip2rs::compress_to_vec(b"bzip2rs", &Bzip2Cli::default()).unwrap();
+/// let concatenated = [compressed.as_slice(), compressed.as_slice()].concat();
+/// assert_eq!(b"bzip2rsbzip2rs".to_vec(), bzip2rs::decompress_to_vec(&concatenated).unwrap());
+/// ```
+pub fn decompress_to_vec(data: &[u8]) -> Result<Vec<u8>> {
+    let mut decompressed = vec![];
+    bzip2::decompress(data, &mut decompressed, &cli::Bzip2Cli::default())?;
+    Ok(decompressed)
+}
+
+/// Compresses `reader` into `writer` with `options`, and returns the number of bytes written. `progress` is called with the cumulative number of bytes read from `reader`,
+/// about once per `--buffer-size` bytes.
+///
+/// ```
+/// use bzip2rs::cli::Bzip2Cli;
+///
+/// let mut compressed = vec![];
+/// let mut read = 0;
+/// bzip2rs::compress_with_progress(&b"bzip2rs"[..], &mut compressed, &Bzip2Cli::default(), Some(&mut |bytes| read = bytes)).unwrap();
+/// assert_eq!(7, read);
+/// ```
+pub fn compress_with_progress(reader: impl std::io::Read, writer: impl Write + Send, options: impl Into<CompressionOptions>, progress: Option<&mut dyn FnMut(u64)>) -> Result<u64> {
+    bzip2::compress(bzip2::ProgressReader::new(reader, progress), writer, &options.into()).map(|stats| stats.written)
+}
+
+/// Decompresses `reader` into `writer`, and returns the number of bytes written. `progress`
+/// is called with the cumulative number of compressed bytes read from `reader`, about once
+/// per `--buffer-size` bytes.
+pub fn decompress_with_progress(reader: impl std::io::Read, writer: impl Write, cli: &cli::Bzip2Cli, progress: Option<&mut dyn FnMut(u64)>) -> Result<u64> {
+    bzip2::decompress(bzip2::ProgressReader::new(reader, progress), writer, cli).map(|stats| stats.written)
+}
+
+/// Decompresses `reader` one block at a time, yielding each decompressed block once it is
+/// decoded, so that huge archives can be processed in constant memory. Only available with
+/// the pure Rust backend.
+///
+/// ```
+/// use bzip2rs::cli::Bzip2Cli;
+///
+/// let compressed = bzip2rs::compress_to_vec(b"bzip2rs", &Bzip2Cli::default()).unwrap();
+/// let blocks = bzip2rs::blocks(compressed.as_slice()).collect::<Result<Vec<_>, _>>().unwrap();
+/// assert_eq!(vec![b"bzip2rs".to_vec()], blocks);
+/// ```
+#[cfg(not(feature = "sys"))]
+pub fn blocks<R: std::io::Read>(reader: R) -> impl Iterator<Item = Result<Vec<u8>>> {
+    bzip2::blocks(reader, &cli::Bzip2Cli::default())
+}
+
+/// Runs the `bzip2rs` command with the given command line arguments, including the program name.
+pub fn do_main<S: AsRef<str>>(args: Vec<S>) -> Result<()> {
+    let args = args.into_iter().map(|s| s.as_ref().to_string()).collect::<Vec<String>>();
+    let pname = args[0].split('/').next_back().unwrap_or(&args[0]);
+    let mut cli = cli::Bzip2Cli::parse_from(&args);
+    if cli.init() {
+        return Ok(());
+    }
+    if cli.self_test {
+        return self_test(&mut std::io::stdout());
+    }
+    let mut walk_errs = vec![];
+    let files = walk::expand_inputs(&cli, &mut walk_errs)?;
+    if files.is_empty() && !cli.is_empty() {
+        // do not fall back to stdin when the directories hold nothing to process.
+        log::warn!("bzip2: no files to process");
+        return Error::error_or((), walk_errs);
+    }
+    if cli.output.as_deref().is_some_and(|output| output != "-") && files.len() != 1 {
+        return Err(Error::InvalidInput("bzip2: --output PATH takes a single input file".to_string()));
+    }
+    cli.input_files = files;
+    // the trailer is checked against all of the output, so the output must hold a single file.
+    if cli.checksum_trailer && cli.input_files.len() > 1 && cli.is_stdout(pname) && matches!(cli.mode(pname), cli::Mode::Compress) {
+        return Err(Error::InvalidInput("bzip2: --checksum-trailer takes a single input file when writing to stdout".to_string()));
+    }
+    cli.threads = cli.resolved_threads();
+    log::info!("using {} thread(s)", cli.threads);
+    let mut timings = vec![];
+    let result = match cli.mode(pname) {
+        cli::Mode::Compress | cli::Mode::Decompress if cli.auto && !cli.is_empty() => perform_auto(&cli, pname, &mut timings).and_then(BatchOutcome::into_result),
+        cli::Mode::Compress => perform_compress(&cli, pname, &mut timings).and_then(BatchOutcome::into_result),
+        cli::Mode::Decompress => perform_decompress(&cli, pname, &mut timings).and_then(BatchOutcome::into_result),
+        cli::Mode::Test => perform_test(&cli, &mut timings),
+        cli::Mode::List => perform_list(&cli, &mut timings),
+        cli::Mode::Benchmark => perform_benchmark(&cli),
+        cli::Mode::Compare => perform_compare(&cli),
+    };
+    if cli.time && !cli.json {
+        eprint!("{}", format_timings(&timings));
+    }
+    if let Err(e) = result {
+        walk_errs.push(e);
+    }
+    let result = Error::error_or((), walk_errs);
+    match cli.quiet {
+        0 => result,
+        1 => result.map_err(Error::quieted),
+        _ => result.map_err(|e| Error::Silenced(Box::new(e))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn test_integrity_ok() {
+        let file = "testdata/e.txt.bz2";
+        assert!(do_main(vec!["bzip2rs", "-t", file]).is_ok());
+    }
+
+    #[test]
+    fn test_integrity_ng() {
+        let file = "testdata/fail-issue5747.bz2.base64";
+        assert!(do_main(vec!["bzip2rs", "-t", file]).is_err());
+    }
+
+    #[test]
+    fn test_decompress() {
+        std::fs::copy("testdata/e.txt.bz2", "testdata/e2.txt.bz2")
+            .expect("failed to copy test file");
+        let r = do_main(vec!["bzip2rs", "testdata/e2.txt.bz2"]);
+        assert!(r.is_ok());
+        let result = Path::new("testdata/e2.txt");
+        assert!(result.exists());
+        assert!(result.is_file());
+        assert!(! Path::new("testdata/e2.txt.bz2").exists());
+        std::fs::remove_file(result)
+            .expect("failed to remove test file");
+    }
+
+    #[test]
+    fn test_compress() {
+        std::fs::copy("testdata/alice-in-wonderland.txt", "testdata/alice-in-wonderland-copy.txt")
+            .expect("failed to copy test file");
+        let r = do_main(vec!["bzip2rs", "testdata/alice-in-wonderland-copy.txt"]);
+        assert!(r.is_ok());
+        let result = Path::new("testdata/alice-in-wonderland-copy.txt.bz2");
+        assert!(result.exists());
+        assert!(result.is_file());
+        assert!(! Path::new("testdata/alice-in-wonderland-copy.txt").exists());
+        std::fs::remove_file(result)
+            .expect("failed to remove test file");
+    }
+
+    #[test]
+    fn test_compress_and_decompress() {
+        std::fs::copy("testdata/alice-in-wonderland.txt", "testdata/alice-in-wonderland-copy2.txt")
+            .expect("failed to copy test file");
+        let r = do_main(vec!["bzip2rs", "testdata/alice-in-wonderland-copy2.txt"]);
+        assert!(r.is_ok());
+        assert!(Path::new("testdata/alice-in-wonderland-copy2.txt.bz2").exists());
+        assert!(! Path::new("testdata/alice-in-wonderland-copy2.txt").exists());
+        let r = do_main(vec!["bzip2rs", "testdata/alice-in-wonderland-copy2.txt.bz2"]);
+        assert!(r.is_ok());
+        assert!(! Path::new("testdata/alice-in-wonderland-copy2.txt.bz2").exists());
+        assert!(Path::new("testdata/alice-in-wonderland-copy2.txt").exists());
+
+        let expected = std::fs::read_to_string("testdata/alice-in-wonderland.txt")
+            .expect("failed to read test file");
+        let actual = std::fs::read_to_string("testdata/alice-in-wonderland-copy2.txt")
+            .expect("failed to read test file");
+        assert_eq!(expected, actual);
+
+        std::fs::remove_file("testdata/alice-in-wonderland-copy2.txt")
+            .expect("failed to remove test file");
+    }
+
+    #[test]
+    fn test_buffer_size_does_not_change_output() {
+        let input = std::fs::read("testdata/alice-in-wonderland.txt")
+            .expect("failed to read test file");
+        let mut outputs = vec![];
+        for size in ["1", "64", "1024"] {
+            let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "--buffer-size", size]);
+            let mut compressed = vec![];
+            assert!(bzip2::compress(input.as_slice(), &mut compressed, &CompressionOptions::from(&cli)).is_ok());
+            let mut decompressed = vec![];
+            assert!(bzip2::decompress(compressed.as_slice(), &mut decompressed, &cli).is_ok());
+            assert_eq!(input, decompressed);
+            outputs.push(compressed);
+        }
+        assert!(outputs.windows(2).all(|w| w[0] == w[1]));
+    }
+
+    struct NoSpaceWriter;
+
+    impl std::io::Write for NoSpaceWriter {
+        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::StorageFull.into())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_no_space_keeps_input() {
+        let file = "testdata/alice-in-wonderland-nospace.txt";
+        let dest = "testdata/alice-in-wonderland-nospace.txt.bz2";
+        std::fs::copy("testdata/alice-in-wonderland.txt", file)
+            .expect("failed to copy test file");
+        std::fs::write(dest, b"BZh9")
+            .expect("failed to create partial output");
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs"]);
+        let input = std::fs::File::open(file)
+            .expect("failed to open test file");
+        let result = bzip2::compress(input, NoSpaceWriter, &CompressionOptions::from(&cli));
+        let mut errs = vec![];
+        finish_each(file, Some(dest), result, &mut errs, &cli, "bzip2rs", "Compressed");
+        assert!(matches!(errs.as_slice(), [Error::NoSpaceLeft(_)]));
+        assert!(Path::new(file).exists());
+        assert!(! Path::new(dest).exists());
+        std::fs::remove_file(file)
+            .expect("failed to remove test file");
+    }
+
+    struct RecordingOutput {
+        synced: std::cell::Cell<bool>,
+        len: u64,
+    }
+
+    impl Durable for RecordingOutput {
+        fn sync_all(&self) -> std::io::Result<()> {
+            self.synced.set(true);
+            Ok(())
+        }
+
+        fn len(&self) -> std::io::Result<u64> {
+            Ok(self.len)
+        }
+    }
+
+    #[test]
+    fn test_sync_before_delete() {
+        let file = "testdata/alice-in-wonderland-sync.txt";
+        std::fs::copy("testdata/alice-in-wonderland.txt", file)
+            .expect("failed to copy test file");
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs"]);
+
+        let empty = RecordingOutput { synced: std::cell::Cell::new(false), len: 0 };
+        let mut errs = vec![];
+        let result = sync_output(&empty, "empty.bz2", None, &cli).map(|_| bzip2::Stats::default());
+        finish_each(file, None, result, &mut errs, &cli, "bzip2rs", "Compressed");
+        assert!(empty.synced.get());
+        assert_eq!(1, errs.len());
+        assert!(Path::new(file).exists());
+
+        let output = RecordingOutput { synced: std::cell::Cell::new(false), len: 42 };
+        let mut errs = vec![];
+        let result = sync_output(&output, "output.bz2", None, &cli).map(|_| bzip2::Stats::default());
+        assert!(output.synced.get());
+        finish_each(file, None, result, &mut errs, &cli, "bzip2rs", "Compressed");
+        assert!(errs.is_empty());
+        assert!(! Path::new(file).exists());
+
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "--sync=false"]);
+        let output = RecordingOutput { synced: std::cell::Cell::new(false), len: 42 };
+        assert!(sync_output(&output, "output.bz2", Some(42), &cli).is_ok());
+        assert!(! output.synced.get());
+    }
+
+    #[test]
+    fn test_integrity_parallel() {
+        let cli = cli::Bzip2Cli::parse_from([
+            "bzip2rs", "-t", "--threads", "3",
+            "testdata/e.txt.bz2",
+            "testdata/not-found.bz2",
+            "testdata/alice_in_wonderland.txt.bz2",
+            "testdata/alice-in-wonderland.txt",
+            "testdata/e.txt.bz2",
+        ]);
+        let results = test_files(&cli);
+        assert_eq!(5, results.len());
+        assert!(results[0].0.is_ok());
+        assert!(results[1].0.is_err());
+        assert!(results[2].0.is_ok());
+        assert!(results[3].0.is_err());
+        assert!(results[4].0.is_ok());
+        assert!(perform_test(&cli, &mut vec![]).is_err());
+    }
+
+    #[test]
+    fn test_stream_count() {
+        let data = std::fs::read("testdata/e.txt.bz2")
+            .expect("failed to read test file");
+        assert!(matches!(bzip2::stream_count(data.as_slice()), Ok(1)));
+        let concatenated = [data.as_slice(), data.as_slice()].concat();
+        assert!(matches!(bzip2::stream_count(concatenated.as_slice()), Ok(2)));
+        assert!(do_main(vec!["bzip2rs", "--list", "testdata/e.txt.bz2"]).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_decompress_sparse() {
+        use std::os::unix::fs::MetadataExt;
+
+        let mut data = vec![0u8; 4 * 1024 * 1024];
+        data.extend_from_slice(b"end of data");
+        data.extend_from_slice(&[0u8; 1024 * 1024]);
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs"]);
+        let mut compressed = vec![];
+        assert!(bzip2::compress(data.as_slice(), &mut compressed, &CompressionOptions::from(&cli)).is_ok());
+        std::fs::write("testdata/zeros-sparse.bin.bz2", compressed)
+            .expect("failed to write test file");
+
+        let r = do_main(vec!["bzip2rs", "-d", "--sparse", "testdata/zeros-sparse.bin.bz2"]);
+        assert!(r.is_ok());
+        let result = Path::new("testdata/zeros-sparse.bin");
+        let metadata = std::fs::metadata(result)
+            .expect("failed to stat test file");
+        assert_eq!(data.len() as u64, metadata.len());
+        assert!(metadata.blocks() * 512 < metadata.len());
+        assert_eq!(data, std::fs::read(result).expect("failed to read test file"));
+        std::fs::remove_file(result)
+            .expect("failed to remove test file");
+    }
+
+    #[test]
+    fn test_expansion_warning() {
+        // a xorshift sequence is random enough to be incompressible.
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let data = (0..64 * 1024).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 56) as u8
+        }).collect::<Vec<u8>>();
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs"]);
+        let mut compressed = vec![];
+        let stats = match bzip2::compress(data.as_slice(), &mut compressed, &CompressionOptions::from(&cli)) {
+            Ok(stats) => stats,
+            Err(e) => panic!("compression failed: {e}"),
+        };
+        assert_eq!(data.len() as u64, stats.read);
+        assert_eq!(compressed.len() as u64, stats.written);
+        assert!(expansion_warning("random.bin", &stats).is_some_and(|w| w.contains("did not compress")));
+
+        let text = std::fs::read("testdata/alice-in-wonderland.txt")
+            .expect("failed to read test file");
+        let stats = match bzip2::compress(text.as_slice(), std::io::sink(), &CompressionOptions::from(&cli)) {
+            Ok(stats) => stats,
+            Err(e) => panic!("compression failed: {e}"),
+        };
+        assert!(expansion_warning("alice-in-wonderland.txt", &stats).is_none());
+    }
+
+    #[test]
+    fn test_concatenated_streams() {
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "-c"]);
+        let parts = [
+            b"first part\n".to_vec(),
+            std::fs::read("testdata/alice-in-wonderland.txt").expect("failed to read test file"),
+            b"third part\n".to_vec(),
+        ];
+        // compress each part onto the same writer, as `bzip2rs -c a b c` does with stdout.
+        let mut stdout = vec![];
+        for part in &parts {
+            assert!(bzip2::compress(part.as_slice(), &mut stdout, &CompressionOptions::from(&cli)).is_ok());
+        }
+        assert!(matches!(bzip2::stream_count(stdout.as_slice()), Ok(3)));
+        let mut decompressed = vec![];
+        assert!(bzip2::decompress(stdout.as_slice(), &mut decompressed, &cli).is_ok());
+        assert_eq!(parts.concat(), decompressed);
+    }
+
+    #[test]
+    fn test_interactive_overwrite() {
+        let file = "testdata/alice-in-wonderland-interactive.txt";
+        let dest = "testdata/alice-in-wonderland-interactive.txt.bz2";
+        std::fs::copy("testdata/alice-in-wonderland.txt", file)
+            .expect("failed to copy test file");
+        std::fs::write(dest, b"existing")
+            .expect("failed to create existing output");
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "-i", file]);
+        let mut errs = vec![];
+
+        let mut prompt = vec![];
+        assert!(! can_overwrite_with(dest, &cli, &mut errs, &mut b"n\n".as_slice(), &mut prompt));
+        assert!(String::from_utf8_lossy(&prompt).contains("overwrite"));
+        assert_eq!(b"existing".to_vec(), std::fs::read(dest).expect("failed to read output"));
+
+        assert!(can_overwrite_with(dest, &cli, &mut errs, &mut b"y\n".as_slice(), &mut vec![]));
+        compress(file, dest, &mut errs, &cli, "bzip2rs");
+        assert!(errs.is_empty());
+        assert!(std::fs::read(dest).expect("failed to read output").starts_with(b"BZh"));
+        std::fs::remove_file(dest)
+            .expect("failed to remove test file");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_force_overwrites_readonly_output() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let file = "testdata/alice-in-wonderland-readonly.txt";
+        let dest = "testdata/alice-in-wonderland-readonly.txt.bz2";
+        std::fs::copy("testdata/alice-in-wonderland.txt", file)
+            .expect("failed to copy test file");
+        std::fs::write(dest, b"read-only")
+            .expect("failed to create existing output");
+        std::fs::set_permissions(dest, std::fs::Permissions::from_mode(0o444))
+            .expect("failed to change permissions");
+        let r = do_main(vec!["bzip2rs", "-f", file]);
+        assert!(r.is_ok());
+        assert!(std::fs::read(dest).expect("failed to read output").starts_with(b"BZh"));
+        assert!(! Path::new(file).exists());
+        std::fs::remove_file(dest)
+            .expect("failed to remove test file");
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_async_roundtrip() {
+        let data = std::fs::read("testdata/alice-in-wonderland.txt")
+            .expect("failed to read test file");
+        let mut compressed = vec![];
+        let written = compress_async(data.as_slice(), &mut compressed, &cli::Bzip2Cli::default()).await
+            .expect("failed to compress");
+        assert_eq!(compressed.len() as u64, written);
+        let mut decompressed = vec![];
+        decompress_async(compressed.as_slice(), &mut decompressed).await
+            .expect("failed to decompress");
+        assert_eq!(data, decompressed);
+    }
+
+    #[test]
+    fn test_streaming_encoder() {
+        let data = std::fs::read("testdata/alice-in-wonderland.txt")
+            .expect("failed to read test file");
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "-b", "1"]);
+        let mut stream = encoder(vec![], &cli);
+        for chunk in data.chunks(7) {
+            stream.write_all(chunk)
+                .expect("failed to write chunk");
+        }
+        let compressed = stream.finish()
+            .expect("failed to finish encoder");
+        assert_eq!(data, decompress_to_vec(&compressed).expect("failed to decompress"));
+
+        let empty = encoder(vec![], &cli).finish()
+            .expect("failed to finish encoder");
+        assert!(decompress_to_vec(&empty).is_ok_and(|d| d.is_empty()));
+    }
+
+    #[cfg(not(feature = "sys"))]
+    #[test]
+    fn test_decode_blocks() {
+        let data = std::fs::read("testdata/alice-in-wonderland.txt")
+            .expect("failed to read test file");
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "-b", "1"]);
+        let compressed = compress_to_vec(&data, &cli)
+            .expect("failed to compress");
+        let mut blocks = vec![];
+        let mut decompressed = vec![];
+        let r = bzip2::decode_blocks(compressed.as_slice(), &mut decompressed, &cli, &mut |stream, block, bytes| {
+            blocks.push((stream, block, bytes));
+        });
+        assert!(r.is_ok());
+        assert_eq!(data, decompressed);
+        assert!(blocks.len() > 1);
+        assert_eq!(data.len() as u64, blocks.iter().map(|(_, _, bytes)| bytes).sum::<u64>());
+    }
+
+    #[test]
+    fn test_decompress_unknown_suffix() {
+        std::fs::copy("testdata/e.txt.bz2", "testdata/e-unknown.dat")
+            .expect("failed to copy test file");
+        let r = do_main(vec!["bzip2rs", "-d", "-q", "testdata/e-unknown.dat"]);
+        assert!(r.is_ok());
+        let result = Path::new("testdata/e-unknown.dat.out");
+        assert!(result.is_file());
+        assert_eq!("error", cli::Bzip2Cli::parse_from(["bzip2rs", "-q"]).log_level());
+        std::fs::remove_file(result)
+            .expect("failed to remove test file");
+    }
+
+    #[test]
+    fn test_work_factor() {
+        let data = std::fs::read("testdata/alice-in-wonderland.txt")
+            .expect("failed to read test file");
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "--workfactor", "30"]);
+        assert_eq!(Some(30), cli.work_factor);
+        let compressed = compress_to_vec(&data, &cli)
+            .expect("failed to compress");
+        assert!(decompress_to_vec(&compressed).is_ok_and(|d| d == data));
+        assert!(cli::Bzip2Cli::try_parse_from(["bzip2rs", "--workfactor", "251"]).is_err());
+    }
+
+    #[test]
+    fn test_empty_file() {
+        std::fs::write("testdata/empty.txt", b"")
+            .expect("failed to create test file");
+        assert!(do_main(vec!["bzip2rs", "testdata/empty.txt"]).is_ok());
+        let compressed = std::fs::read("testdata/empty.txt.bz2")
+            .expect("failed to read compressed file");
+        assert_eq!(14, compressed.len());
+        assert!(compressed.starts_with(b"BZh"));
+        assert!(do_main(vec!["bzip2rs", "-t", "testdata/empty.txt.bz2"]).is_ok());
+        assert!(do_main(vec!["bzip2rs", "-d", "testdata/empty.txt.bz2"]).is_ok());
+        assert_eq!(Some(0), std::fs::metadata("testdata/empty.txt").ok().map(|m| m.len()));
+        std::fs::remove_file("testdata/empty.txt")
+            .expect("failed to remove test file");
+    }
+
+    #[test]
+    fn test_decompress_zero_length_input() {
+        std::fs::write("testdata/zero.bz2", b"")
+            .expect("failed to create test file");
+        assert!(do_main(vec!["bzip2rs", "-d", "testdata/zero.bz2"]).is_err());
+        assert!(!Path::new("testdata/zero").exists());
+        assert!(Path::new("testdata/zero.bz2").exists());
+        std::fs::remove_file("testdata/zero.bz2")
+            .expect("failed to remove test file");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_read_fifo() {
+        let fifo = "testdata/fifo-input";
+        let _ = std::fs::remove_file(fifo);
+        let status = std::process::Command::new("mkfifo").arg(fifo).status()
+            .expect("failed to run mkfifo");
+        assert!(status.success());
+        assert_eq!(Some("a FIFO"), special_file_kind(fifo));
+        let data = std::fs::read("testdata/alice-in-wonderland.txt")
+            .expect("failed to read test file");
+        let writer = std::thread::spawn({
+            let data = data.clone();
+            move || std::fs::write(fifo, data)
+        });
+        assert!(do_main(vec!["bzip2rs", "-q", fifo]).is_ok());
+        assert!(writer.join().is_ok_and(|r| r.is_ok()));
+        assert!(Path::new(fifo).exists());
+        let compressed = std::fs::read("testdata/fifo-input.bz2")
+            .expect("failed to read output");
+        assert!(decompress_to_vec(&compressed).is_ok_and(|d| d == data));
+        for file in [fifo, "testdata/fifo-input.bz2"] {
+            std::fs::remove_file(file)
+                .expect("failed to remove test file");
+        }
+    }
+
+    #[test]
+    fn test_case_insensitive_suffix() {
+        assert_eq!(Some("ARCHIVE".to_string()), suffix::original_name("ARCHIVE.BZ2", &[]));
+        assert_eq!(Some("notes.txt".to_string()), suffix::original_name("notes.txt.Bz2", &[]));
+        assert_eq!(Some("backup.tar".to_string()), suffix::original_name("backup.Tbz2", &[]));
+        assert_eq!(Some("BACKUP.TAR".to_string()), suffix::original_name("BACKUP.TBZ", &[]));
+        assert_eq!(None, suffix::original_name("notes.txt", &[]));
+
+        std::fs::copy("testdata/e.txt.bz2", "testdata/e-upper.TXT.BZ2")
+            .expect("failed to copy test file");
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "testdata/e-upper.TXT.BZ2"]);
+        assert!(matches!(cli.mode("bzip2rs"), cli::Mode::Decompress));
+        assert!(do_main(vec!["bzip2rs", "testdata/e-upper.TXT.BZ2"]).is_ok());
+        let result = Path::new("testdata/e-upper.TXT");
+        assert!(result.is_file());
+        std::fs::remove_file(result)
+            .expect("failed to remove test file");
+    }
+
+    #[test]
+    fn test_output_dir() {
+        let dir = "testdata/output-dir";
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::copy("testdata/alice-in-wonderland.txt", "testdata/alice-out-dir.txt")
+            .expect("failed to copy test file");
+        std::fs::copy("testdata/alice-in-wonderland.txt", "testdata/alice-out-dir2.txt")
+            .expect("failed to copy test file");
+        std::fs::copy("testdata/e.txt.bz2", "testdata/e-out-dir.txt.bz2")
+            .expect("failed to copy test file");
+        let r = do_main(vec!["bzip2rs", "-z", "--output-dir", dir, "testdata/alice-out-dir.txt", "testdata/alice-out-dir2.txt"]);
+        assert!(r.is_ok());
+        let r = do_main(vec!["bzip2rs", "-d", "--output-dir", dir, "testdata/e-out-dir.txt.bz2"]);
+        assert!(r.is_ok());
+        assert!(Path::new("testdata/output-dir/alice-out-dir.txt.bz2").is_file());
+        assert!(Path::new("testdata/output-dir/alice-out-dir2.txt.bz2").is_file());
+        assert!(Path::new("testdata/output-dir/e-out-dir.txt").is_file());
+        assert!(Path::new("testdata/alice-out-dir.txt").exists());
+        assert!(Path::new("testdata/alice-out-dir2.txt").exists());
+        assert!(Path::new("testdata/e-out-dir.txt.bz2").exists());
+        std::fs::remove_dir_all(dir)
+            .expect("failed to remove test directory");
+        std::fs::remove_file("testdata/alice-out-dir.txt")
+            .expect("failed to remove test file");
+        std::fs::remove_file("testdata/alice-out-dir2.txt")
+            .expect("failed to remove test file");
+        std::fs::remove_file("testdata/e-out-dir.txt.bz2")
+            .expect("failed to remove test file");
+    }
+
+    #[test]
+    fn test_license() {
+        assert!(do_main(vec!["bzip2rs", "-L"]).is_ok());
+        let license = cli::Bzip2Cli::parse_from(["bzip2rs", "-L"]).banner();
+        assert!(license.is_some_and(|text| text.contains("Permission is hereby granted")));
+        let version = cli::Bzip2Cli::parse_from(["bzip2rs", "-V"]).banner();
+        assert!(version.is_some_and(|text| !text.contains("Permission is hereby granted")));
+    }
+
+    #[test]
+    fn test_completion() {
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "--completion", "bash"]);
+        assert_eq!(Some(clap_complete::Shell::Bash), cli.completion);
+        let mut script = vec![];
+        cli::write_completion(clap_complete::Shell::Bash, &mut script);
+        assert!(String::from_utf8_lossy(&script).contains("--decompress"));
+    }
+
+    #[test]
+    fn test_quiet_file_exists() {
+        std::fs::copy("testdata/e.txt.bz2", "testdata/e-quiet.txt.bz2")
+            .expect("failed to copy test file");
+        std::fs::write("testdata/e-quiet.txt", b"existing")
+            .expect("failed to create test file");
+        let r = do_main(vec!["bzip2rs", "-d", "-q", "testdata/e-quiet.txt.bz2"]);
+        assert!(r.is_err_and(|e| e.is_suppressed()));
+        assert!(Error::FileExists("testdata/e-quiet.txt".to_string()).is_noncritical());
+        assert_eq!(Some(b"existing".to_vec()), std::fs::read("testdata/e-quiet.txt").ok());
+        std::fs::remove_file("testdata/e-quiet.txt")
+            .expect("failed to remove test file");
+        std::fs::remove_file("testdata/e-quiet.txt.bz2")
+            .expect("failed to remove test file");
+    }
+
+    #[test]
+    fn test_verbosity() {
+        let level = |args: &[&str]| cli::Bzip2Cli::parse_from(args).log_level();
+        assert_eq!("error", level(&["bzip2rs", "-q"]));
+        assert_eq!("off", level(&["bzip2rs", "-qq"]));
+        assert_eq!("warn", level(&["bzip2rs"]));
+        assert_eq!("trace", level(&["bzip2rs", "-vv"]));
+        assert_eq!("info", level(&["bzip2rs", "-q", "-vv"]));
+        assert_eq!("info", level(&["bzip2rs", "-vv", "-q"]));
+    }
+
+    #[test]
+    fn test_no_color() {
+        let no_color = std::ffi::OsString::from("1");
+        let color = cli::color_enabled(Some(&no_color), true);
+        assert!(!color);
+        let line = cli::colorize(log::Level::Warn, "bzip2: warning", color);
+        assert!(!line.contains('\x1b'));
+        let line = cli::colorize(log::Level::Error, "bzip2: error", cli::color_enabled(None, true));
+        assert!(line.contains('\x1b'));
+        assert!(!cli::color_enabled(None, false));
+    }
+
+    #[test]
+    fn test_parallel_compress() {
+        let data = std::fs::read("testdata/alice-in-wonderland.txt")
+            .expect("failed to read test file")
+            .repeat(4);
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "--fast", "--threads", "4"]);
+        let compressed = compress_to_vec(&data, &cli)
+            .expect("failed to compress");
+        #[cfg(not(feature = "sys"))]
+        assert_eq!(data.len().div_ceil(100_000), bzip2::stream_count(compressed.as_slice()).unwrap_or(0));
+        assert!(decompress_to_vec(&compressed).is_ok_and(|d| d == data));
+    }
+
+    #[test]
+    fn test_blocksize_bytes() {
+        let data = std::fs::read("testdata/alice-in-wonderland.txt")
+            .expect("failed to read test file");
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "--blocksize-bytes", "450000"]);
+        assert_eq!(5, cli.compress_level());
+        let compressed = compress_to_vec(&data, &cli)
+            .expect("failed to compress");
+        assert!(compressed.starts_with(b"BZh5"));
+        assert!(decompress_to_vec(&compressed).is_ok_and(|d| d == data));
+        assert!(cli::Bzip2Cli::try_parse_from(["bzip2rs", "--blocksize-bytes", "99999"]).is_err());
+        assert!(cli::Bzip2Cli::try_parse_from(["bzip2rs", "--blocksize-bytes", "900001"]).is_err());
+    }
+
+    #[test]
+    fn test_estimate_memory() {
+        assert_eq!((7600, 3700), bzip2::estimate_memory(9, false));
+        assert_eq!((7600, 2350), bzip2::estimate_memory(9, true));
+        assert_eq!((1200, 500), bzip2::estimate_memory(1, false));
+    }
+
+    #[test]
+    fn test_thorough_integrity() {
+        let compressed = std::fs::read("testdata/e.txt.bz2")
+            .expect("failed to read test file");
+        let mut original = decompress_to_vec(&compressed)
+            .expect("failed to decompress");
+        std::fs::write("testdata/e-thorough.txt.bz2", &compressed)
+            .expect("failed to create test file");
+        std::fs::write("testdata/e-thorough.txt", &original)
+            .expect("failed to create test file");
+        assert!(do_main(vec!["bzip2rs", "-tt", "testdata/e-thorough.txt.bz2"]).is_ok());
+        original[1000] ^= 1;
+        std::fs::write("testdata/e-thorough.txt", &original)
+            .expect("failed to create test file");
+        assert!(do_main(vec!["bzip2rs", "-t", "testdata/e-thorough.txt.bz2"]).is_ok());
+        let r = do_main(vec!["bzip2rs", "-tt", "testdata/e-thorough.txt.bz2"]);
+        assert!(r.is_err_and(|e| e.to_string().contains("at byte 1000")));
+        original[1000] ^= 1;
+        std::fs::write("testdata/e-thorough.txt", &original[..original.len() - 1])
+            .expect("failed to create test file");
+        assert!(do_main(vec!["bzip2rs", "-tt", "testdata/e-thorough.txt.bz2"]).is_err());
+        std::fs::remove_file("testdata/e-thorough.txt")
+            .expect("failed to remove test file");
+        std::fs::remove_file("testdata/e-thorough.txt.bz2")
+            .expect("failed to remove test file");
+    }
+
+    #[test]
+    fn test_digest() {
+        let digest = |algo: &str| {
+            let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "-t", "--digest", algo, "testdata/e.txt.bz2"]);
+            test_files(&cli).pop().and_then(|(r, _)| r.ok()).and_then(|tested| tested.digest)
+        };
+        let sha256 = digest("sha256");
+        assert_eq!(Some("b2fdec07c4f495548588e2c178bb9d1dbdb76ba8190ea633dc96722cac77cb2c"), sha256.as_deref());
+        assert_eq!(sha256, digest("sha256"));
+        assert_eq!(Some("8dc3bbcb"), digest("crc32").as_deref());
+    }
+
+    #[test]
+    fn test_timings() {
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "-t", "--time", "testdata/e.txt.bz2", "testdata/alice_in_wonderland.txt.bz2"]);
+        assert!(cli.time);
+        let mut timings = vec![];
+        assert!(perform_test(&cli, &mut timings).is_ok());
+        let table = format_timings(&timings);
+        let rows = table.lines().skip(1).collect::<Vec<_>>();
+        assert_eq!(2, rows.len());
+        assert!(rows[0].starts_with("testdata/e.txt.bz2") && rows[0].contains("43149"));
+        assert!(rows[1].starts_with("testdata/alice_in_wonderland.txt.bz2") && rows[1].contains("42770"));
+    }
+
+    #[test]
+    fn test_compress_in_blocks() {
+        let data = std::fs::read("testdata/alice-in-wonderland.txt")
+            .expect("failed to read test file")
+            .repeat(2);
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "--fast"]);
+        let mut compressed = vec![];
+        let stats = bzip2::compress(data.as_slice(), &mut compressed, &CompressionOptions::from(&cli))
+            .expect("failed to compress");
+        assert_eq!(data.len() as u64, stats.read);
+        #[cfg(not(feature = "sys"))]
+        assert_eq!(data.len().div_ceil(100_000), bzip2::stream_count(compressed.as_slice()).unwrap_or(0));
+        assert!(decompress_to_vec(&compressed).is_ok_and(|d| d == data));
+    }
+
+    /// A reader failing every other read with `Interrupted`, as a signal would.
+    struct InterruptingReader<R> {
+        inner: R,
+        interrupt: bool,
+    }
+
+    impl<R: std::io::Read> std::io::Read for InterruptingReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.interrupt = !self.interrupt;
+            if self.interrupt {
+                Err(std::io::ErrorKind::Interrupted.into())
+            } else {
+                self.inner.read(buf)
+            }
+        }
+    }
+
+    #[test]
+    fn test_retry_interrupted() {
+        let data = std::fs::read("testdata/alice-in-wonderland.txt")
+            .expect("failed to read test file");
+        let cli = cli::Bzip2Cli::default();
+        let mut compressed = vec![];
+        let reader = InterruptingReader { inner: data.as_slice(), interrupt: false };
+        assert!(bzip2::compress(reader, &mut compressed, &CompressionOptions::from(&cli)).is_ok());
+        let mut decompressed = vec![];
+        let reader = InterruptingReader { inner: compressed.as_slice(), interrupt: false };
+        assert!(bzip2::decompress(reader, &mut decompressed, &cli).is_ok());
+        assert_eq!(data, decompressed);
+        let reader = InterruptingReader { inner: compressed.as_slice(), interrupt: false };
+        assert!(bzip2::test_integrity(reader, &cli).is_ok());
+    }
+
+    #[test]
+    fn test_custom_suffix() {
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "-S", ".cmp", "testdata/e-suffix.txt.cmp"]);
+        assert!(matches!(cli.mode("bzip2rs"), cli::Mode::Decompress));
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "testdata/e-suffix.txt.cmp"]);
+        assert!(matches!(cli.mode("bzip2rs"), cli::Mode::Compress));
+
+        std::fs::copy("testdata/alice-in-wonderland.txt", "testdata/alice-suffix.txt")
+            .expect("failed to copy test file");
+        assert!(do_main(vec!["bzip2rs", "-S", ".cmp", "testdata/alice-suffix.txt"]).is_ok());
+        assert!(Path::new("testdata/alice-suffix.txt.cmp").is_file());
+        assert!(do_main(vec!["bzip2rs", "-S", ".cmp", "testdata/alice-suffix.txt.cmp"]).is_ok());
+        let result = Path::new("testdata/alice-suffix.txt");
+        assert!(result.is_file());
+        std::fs::remove_file(result)
+            .expect("failed to remove test file");
+    }
+
+    #[test]
+    fn test_progress_callback() {
+        let data = std::fs::read("testdata/alice-in-wonderland.txt")
+            .expect("failed to read test file");
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "--buffer-size", "16"]);
+        let mut reported = vec![];
+        let mut compressed = vec![];
+        let r = compress_with_progress(data.as_slice(), &mut compressed, &cli, Some(&mut |bytes| reported.push(bytes)));
+        assert!(r.is_ok_and(|written| written == compressed.len() as u64));
+        assert!(reported.len() > 1);
+        assert!(reported.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(Some(&(data.len() as u64)), reported.last());
+
+        let mut reported = vec![];
+        let mut decompressed = vec![];
+        let r = decompress_with_progress(compressed.as_slice(), &mut decompressed, &cli, Some(&mut |bytes| reported.push(bytes)));
+        assert!(r.is_ok());
+        assert_eq!(data, decompressed);
+        assert_eq!(Some(&(compressed.len() as u64)), reported.last());
+    }
+
+    #[test]
+    fn test_recursive_filter() {
+        let dir = "testdata/walk";
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all("testdata/walk/sub/deeper")
+            .expect("failed to create test directory");
+        for file in ["a.log", "b.txt", "sub/c.log", "sub/deeper/d.log"] {
+            std::fs::write(Path::new(dir).join(file), b"log line\n")
+                .expect("failed to create test file");
+        }
+        let r = do_main(vec!["bzip2rs", "-z", "-r", "--include", "*.log", "--max-depth", "2", dir]);
+        assert!(r.is_ok());
+        assert!(Path::new("testdata/walk/a.log.bz2").is_file());
+        assert!(Path::new("testdata/walk/sub/c.log.bz2").is_file());
+        assert!(Path::new("testdata/walk/b.txt").is_file());
+        assert!(!Path::new("testdata/walk/b.txt.bz2").exists());
+        assert!(Path::new("testdata/walk/sub/deeper/d.log").is_file());
+        assert!(!Path::new("testdata/walk/sub/deeper/d.log.bz2").exists());
+        std::fs::remove_dir_all(dir)
+            .expect("failed to remove test directory");
+    }
+
+    #[test]
+    #[cfg(feature = "sys")]
+    fn test_integrity_small() {
+        let bytes = |args: &[&str]| test_files(&cli::Bzip2Cli::parse_from(args))
+            .into_iter()
+            .map(|(r, _)| r.map(|tested| tested.bytes).ok())
+            .collect::<Vec<_>>();
+        let small = bytes(&["bzip2rs", "-t", "-s", "testdata/e.txt.bz2", "testdata/alice_in_wonderland.txt.bz2"]);
+        assert!(small.iter().all(Option::is_some));
+        assert_eq!(bytes(&["bzip2rs", "-t", "testdata/e.txt.bz2", "testdata/alice_in_wonderland.txt.bz2"]), small);
+    }
+
+    #[test]
+    fn test_keep() {
+        let keep = |args: &[&str], program_name: &str| cli::Bzip2Cli::parse_from(args).keep(program_name);
+        assert!(!keep(&["bzip2rs", "file"], "bzip2rs"));
+        assert!(keep(&["bzip2rs", "-k", "file"], "bzip2rs"));
+        assert!(keep(&["bzip2rs", "-c", "file"], "bzip2rs"));
+        assert!(keep(&["bzip2rs", "-d", "-c", "file.bz2"], "bzip2rs"));
+        assert!(keep(&["bzcat", "file.bz2"], "bzcat"));
+        assert!(!keep(&["bunzip2", "file.bz2"], "bunzip2"));
+        assert!(keep(&["bzip2rs", "--output-dir", "out", "file"], "bzip2rs"));
+    }
+
+    #[test]
+    fn test_stdout_keeps_input() {
+        std::fs::copy("testdata/e.txt.bz2", "testdata/e-stdout.txt.bz2")
+            .expect("failed to copy test file");
+        let cli = cli::Bzip2Cli::parse_from(["bzcat", "testdata/e-stdout.txt.bz2"]);
+        let mut errs = vec![];
+        finish_each("testdata/e-stdout.txt.bz2", None, Ok(bzip2::Stats::default()), &mut errs, &cli, "bzcat", "Decompressed");
+        assert!(errs.is_empty());
+        assert!(Path::new("testdata/e-stdout.txt.bz2").exists());
+        std::fs::remove_file("testdata/e-stdout.txt.bz2")
+            .expect("failed to remove test file");
+    }
+
+    #[test]
+    fn test_conflicting_modes() {
+        let message = |args: &[&str]| cli::Bzip2Cli::try_parse_from(args).err().map(|e| e.to_string());
+        assert!(message(&["bzip2rs", "-z", "-d", "file"]).is_some_and(|m| m.contains("'--compress' cannot be used with '--decompress'")));
+        assert!(message(&["bzip2rs", "-t", "-z", "file"]).is_some_and(|m| m.contains("cannot be used with")));
+        assert!(message(&["bzip2rs", "-d", "-t", "file.bz2"]).is_none());
+    }
+
+    #[test]
+    fn test_list_totals() {
+        let cli = cli::Bzip2Cli::default();
+        let entries = ["testdata/e.txt.bz2", "testdata/alice_in_wonderland.txt.bz2"].iter()
+            .map(|file| (file.to_string(), list_each(file, &cli).expect("failed to list test file")))
+            .collect::<Vec<_>>();
+        let table = format_list(&entries);
+        let columns = |line: &str| line.split_whitespace().map(str::to_string).collect::<Vec<_>>();
+        let rows = table.lines().skip(1).map(columns).collect::<Vec<_>>();
+        assert_eq!(3, rows.len());
+        assert_eq!("(totals)", rows[2][4]);
+        let total = |column: usize| rows[..2].iter().map(|row| row[column].parse::<u64>().unwrap_or(0)).sum::<u64>();
+        assert_eq!(total(0).to_string(), rows[2][0]);
+        assert_eq!(total(1).to_string(), rows[2][1]);
+        assert_eq!(1, format_list(&entries[..1]).lines().count() - 1);
+    }
+
+    #[test]
+    fn test_stored_name() {
+        std::fs::copy("testdata/alice-in-wonderland.txt", "testdata/alice-named.txt")
+            .expect("failed to copy test file");
+        assert!(do_main(vec!["bzip2rs", "-q", "-N", "testdata/alice-named.txt"]).is_ok());
+        std::fs::rename("testdata/alice-named.txt.bz2", "testdata/renamed.bz2")
+            .expect("failed to rename test file");
+        let compressed = std::fs::read("testdata/renamed.bz2")
+            .expect("failed to read test file");
+        // the trailer does not get in the way of decoding.
+        let original = std::fs::read("testdata/alice-in-wonderland.txt")
+            .expect("failed to read test file");
+        assert!(decompress_to_vec(&compressed).is_ok_and(|data| data == original));
+
+        assert!(do_main(vec!["bzip2rs", "-q", "-d", "-N", "testdata/renamed.bz2"]).is_ok());
+        let result = Path::new("testdata/alice-named.txt");
+        assert!(result.is_file());
+        assert!(!Path::new("testdata/renamed").exists());
+        assert_eq!(original, std::fs::read(result).expect("failed to read test file"));
+        std::fs::remove_file(result)
+            .expect("failed to remove test file");
+    }
+
+    #[test]
+    fn test_block_size_line() {
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "-b", "9"]);
+        let compressed = compress_to_vec(b"hello, world", &cli)
+            .expect("failed to compress");
+        assert!(decompress_to_vec(&compressed).is_ok_and(|data| data == b"hello, world"));
+        assert_eq!(Some("block size 900k".to_string()), bzip2::block_size_line(&compressed));
+        assert_eq!(None, bzip2::block_size_line(b"BZrsN"));
+    }
+
+    #[test]
+    #[cfg(feature = "sys")]
+    fn test_small_mode() {
+        let data = std::fs::read("testdata/alice-in-wonderland.txt")
+            .expect("failed to read test file");
+        let compressed = compress_to_vec(&data, &cli::Bzip2Cli::parse_from(["bzip2rs", "-b", "9"]))
+            .expect("failed to compress");
+        let mut decompressed = vec![];
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "-s"]);
+        assert!(decompress_with_progress(compressed.as_slice(), &mut decompressed, &cli, None).is_ok());
+        assert_eq!(data, decompressed);
+
+        // the logger of this process belongs to whichever test ran do_main() first, so the
+        // log is written by a process of its own.
+        let path = "testdata/bzip2rs-small.log";
+        let _ = std::fs::remove_file(path);
+        let status = std::process::Command::new(std::env::current_exe().expect("failed to locate test binary"))
+            .args(["--exact", "tests::small_mode_logged", "--ignored", "--test-threads", "1"])
+            .status()
+            .expect("failed to run test binary");
+        assert!(status.success());
+        let logged = std::fs::read_to_string(path)
+            .expect("failed to read log file");
+        std::fs::remove_file(path)
+            .expect("failed to remove test file");
+        assert!(logged.contains("stream 1: small mode: decoding within 2350k"), "{logged}");
+    }
+
+    /// Tests a file with `-s`, logging to `testdata/bzip2rs-small.log`; run by `test_small_mode`.
+    #[test]
+    #[ignore]
+    #[cfg(feature = "sys")]
+    fn small_mode_logged() {
+        let args = vec!["bzip2rs", "-t", "-s", "-v", "--log-file", "testdata/bzip2rs-small.log", "testdata/alice_in_wonderland.txt.bz2"];
+        assert!(do_main(args).is_ok());
+    }
+
+    #[test]
+    #[cfg(not(feature = "sys"))]
+    fn test_small_mode_unsupported() {
+        let compressed = compress_to_vec(b"hello, world", CompressionOptions::default())
+            .expect("failed to compress");
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "-s"]);
+        let r = decompress_with_progress(compressed.as_slice(), &mut vec![], &cli, None);
+        assert!(r.is_err_and(|e| matches!(e, Error::InvalidInput(_))));
+        let r = bzip2::test_integrity(compressed.as_slice(), &cli);
+        assert!(r.is_err_and(|e| e.to_string().contains("--small needs bzip2rs built with the `sys` feature")));
+    }
+
+    #[cfg(not(feature = "sys"))]
+    #[test]
+    fn test_blocks_iterator() {
+        let data = std::fs::read("testdata/alice-in-wonderland.txt")
+            .expect("failed to read test file");
+        let compressed = compress_to_vec(&data, &cli::Bzip2Cli::parse_from(["bzip2rs", "-b", "1"]))
+            .expect("failed to compress");
+        let decoded = blocks(compressed.as_slice()).collect::<Result<Vec<_>>>()
+            .expect("failed to decompress");
+        assert!(decoded.len() > 1);
+        assert_eq!(data, decoded.concat());
+        assert!(blocks(&b"BZh9"[..]).any(|block| block.is_err()));
+    }
+
+    #[test]
+    fn test_keep_broken() {
+        let compressed = std::fs::read("testdata/alice_in_wonderland.txt.bz2")
+            .expect("failed to read test file");
+        let truncated = &compressed[..compressed.len() / 2];
+        std::fs::write("testdata/alice-broken1.txt.bz2", truncated)
+            .expect("failed to write test file");
+        std::fs::write("testdata/alice-broken2.txt.bz2", truncated)
+            .expect("failed to write test file");
+
+        assert!(do_main(vec!["bzip2rs", "-d", "-q", "--keep-broken", "testdata/alice-broken1.txt.bz2"]).is_err());
+        assert!(Path::new("testdata/alice-broken1.txt.broken").is_file());
+        assert!(!Path::new("testdata/alice-broken1.txt").exists());
+
+        assert!(do_main(vec!["bzip2rs", "-d", "-q", "testdata/alice-broken2.txt.bz2"]).is_err());
+        assert!(!Path::new("testdata/alice-broken2.txt.broken").exists());
+        assert!(!Path::new("testdata/alice-broken2.txt").exists());
+
+        for file in ["testdata/alice-broken1.txt.bz2", "testdata/alice-broken2.txt.bz2", "testdata/alice-broken1.txt.broken"] {
+            std::fs::remove_file(file)
+                .expect("failed to remove test file");
+        }
+    }
+
+    #[test]
+    fn test_compress_skips_compressed_inputs() {
+        std::fs::copy("testdata/alice-in-wonderland.txt", "testdata/foo-mixed.txt")
+            .expect("failed to copy test file");
+        std::fs::copy("testdata/e.txt.bz2", "testdata/bar-mixed.bz2")
+            .expect("failed to copy test file");
+        assert!(do_main(vec!["bzip2rs", "-q", "testdata/foo-mixed.txt", "testdata/bar-mixed.bz2"]).is_ok());
+        assert!(Path::new("testdata/foo-mixed.txt.bz2").is_file());
+        assert!(Path::new("testdata/bar-mixed.bz2").is_file());
+        assert!(!Path::new("testdata/bar-mixed.bz2.bz2").exists());
+        for file in ["testdata/foo-mixed.txt.bz2", "testdata/bar-mixed.bz2"] {
+            std::fs::remove_file(file)
+                .expect("failed to remove test file");
+        }
+    }
+
+    #[test]
+    fn test_trailing_garbage() {
+        let data = b"hello, world".repeat(100);
+        let compressed = compress_to_vec(&data, &cli::Bzip2Cli::default())
+            .expect("failed to compress");
+        assert!(bzip2::ends_with_footer(&compressed));
+        let mut garbage = compressed.clone();
+        garbage.extend_from_slice(b"\0\0junk after the stream");
+        assert!(!bzip2::ends_with_footer(&garbage));
+        // decoding stops at the garbage, even when another stream follows it.
+        garbage.extend_from_slice(&compressed);
+        for args in [&["bzip2rs"][..], &["bzip2rs", "--ignore-trailing-garbage"]] {
+            let mut decompressed = vec![];
+            let cli = cli::Bzip2Cli::parse_from(args);
+            assert!(decompress_with_progress(garbage.as_slice(), &mut decompressed, &cli, None).is_ok());
+            assert_eq!(data, decompressed);
+        }
+    }
+
+    #[test]
+    fn test_strict_trailing_garbage() {
+        let mut garbage = compress_to_vec(b"hello, world", &cli::Bzip2Cli::default())
+            .expect("failed to compress");
+        garbage.extend_from_slice(b"junk");
+        let decompress = |args: &[&str]| decompress_with_progress(garbage.as_slice(), std::io::sink(), &cli::Bzip2Cli::parse_from(args), None);
+        assert!(decompress(&["bzip2rs"]).is_ok());
+        assert!(decompress(&["bzip2rs", "--ignore-trailing-garbage"]).is_ok());
+        let r = decompress(&["bzip2rs", "--ignore-trailing-garbage=false"]);
+        assert!(matches!(r, Err(Error::CorruptData(_))));
+        assert_eq!(2, r.err().map_or(0, |e| e.exit_code()));
+    }
+
+    #[test]
+    fn test_compress_level_precedence() {
+        let level = |args: &[&str]| cli::Bzip2Cli::parse_from(args).compress_level();
+        assert_eq!(6, level(&["bzip2rs"]));
+        assert_eq!(1, level(&["bzip2rs", "--fast"]));
+        assert_eq!(9, level(&["bzip2rs", "--best"]));
+        assert_eq!(3, level(&["bzip2rs", "-b", "3"]));
+        assert_eq!(3, level(&["bzip2rs", "-b", "3", "--fast"]));
+        assert_eq!(3, level(&["bzip2rs", "-b", "3", "--best"]));
+        assert_eq!(4, level(&["bzip2rs", "--blocksize-bytes", "400000", "-b", "3"]));
+        assert_eq!(4, level(&["bzip2rs", "--blocksize-bytes", "400000", "--best"]));
+        assert_eq!(7, level(&["bzip2rs", "--level", "7"]));
+        assert_eq!(7, level(&["bzip2rs", "--level", "7", "-b", "3"]));
+        assert_eq!(7, level(&["bzip2rs", "--level", "7", "--blocksize-bytes", "400000"]));
+        assert_eq!(7, level(&["bzip2rs", "--level", "7", "--fast"]));
+        assert_eq!(7, level(&["bzip2rs", "--level", "7", "--best"]));
+        assert!(cli::Bzip2Cli::try_parse_from(["bzip2rs", "--level", "0"]).is_err());
+        assert!(cli::Bzip2Cli::try_parse_from(["bzip2rs", "--level", "10"]).is_err());
+    }
+
+    #[test]
+    fn test_append() {
+        let file = "testdata/alice-append.txt";
+        std::fs::copy("testdata/alice-in-wonderland.txt", file)
+            .expect("failed to copy test file");
+        assert!(do_main(vec!["bzip2rs", "-q", "-k", file]).is_ok());
+        std::fs::write(file, b"the second file")
+            .expect("failed to write test file");
+        assert!(do_main(vec!["bzip2rs", "-q", "--append", file]).is_ok());
+        let compressed = std::fs::read("testdata/alice-append.txt.bz2")
+            .expect("failed to read output");
+        let mut expected = std::fs::read("testdata/alice-in-wonderland.txt")
+            .expect("failed to read test file");
+        expected.extend_from_slice(b"the second file");
+        assert!(decompress_to_vec(&compressed).is_ok_and(|d| d == expected));
+        std::fs::remove_file("testdata/alice-append.txt.bz2")
+            .expect("failed to remove test file");
+    }
+
+    #[cfg(feature = "both")]
+    #[test]
+    fn test_benchmark() {
+        let data = std::fs::read("testdata/alice-in-wonderland.txt")
+            .expect("failed to read test file");
+        let results = bzip2::benchmark(&data, &cli::Bzip2Cli::default())
+            .expect("failed to benchmark");
+        assert_eq!(vec!["libbzip2", "pure_rust"], results.iter().map(|r| r.backend).collect::<Vec<_>>());
+        for result in &results {
+            assert!(decompress_to_vec(&result.compressed).is_ok_and(|d| d == data));
+        }
+        let (sys, pure) = (results[0].compressed.len() as f64, results[1].compressed.len() as f64);
+        assert!((sys - pure).abs() / sys < 0.1);
+        assert!(format_benchmark("alice", data.len() as u64, &results).contains("pure_rust"));
+    }
+
+    #[test]
+    fn test_logger_leaves_environment() {
+        let before = std::env::var_os("RUST_LOG");
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "-v"]);
+        assert_eq!(log::LevelFilter::Info, cli.level_filter());
+        assert_eq!(log::LevelFilter::Error, cli::Bzip2Cli::parse_from(["bzip2rs", "-q"]).level_filter());
+        assert_eq!(log::LevelFilter::Trace, cli::Bzip2Cli::parse_from(["bzip2rs", "-vv"]).level_filter());
+        assert!(!cli.init());
+        assert!(log::max_level() >= log::LevelFilter::Error);
+        assert_eq!(before, std::env::var_os("RUST_LOG"));
+    }
+
+    #[test]
+    fn test_log_file() {
+        use log::Log;
+
+        let path = "testdata/bzip2rs-test.log";
+        let _ = std::fs::remove_file(path);
+        let (mut builder, warning) = cli::logger(&cli::Bzip2Cli::parse_from(["bzip2rs", "--log-file", path]));
+        assert_eq!(None, warning);
+        let logger = builder.build();
+        logger.log(&log::Record::builder().level(log::Level::Warn).target("bzip2rs").args(format_args!("logged to a file")).build());
+        logger.log(&log::Record::builder().level(log::Level::Info).target("bzip2rs").args(format_args!("filtered out")).build());
+        logger.flush();
+        let logged = std::fs::read_to_string(path)
+            .expect("failed to read log file");
+        assert!(logged.contains("logged to a file"));
+        assert!(!logged.contains("filtered out"));
+        std::fs::remove_file(path)
+            .expect("failed to remove test file");
+
+        let (_, warning) = cli::logger(&cli::Bzip2Cli::parse_from(["bzip2rs", "--log-file", "testdata/no-such-dir/test.log"]));
+        assert!(warning.is_some_and(|w| w.contains("logging to stderr")));
+    }
+
+    #[test]
+    fn test_log_timestamps() {
+        use log::Log;
+
+        let logged = |args: &[&str], path: &str| {
+            let _ = std::fs::remove_file(path);
+            let (mut builder, _) = cli::logger(&cli::Bzip2Cli::parse_from(args));
+            let logger = builder.build();
+            logger.log(&log::Record::builder().level(log::Level::Warn).target("bzip2rs").args(format_args!("timed")).build());
+            logger.flush();
+            let line = std::fs::read_to_string(path)
+                .expect("failed to read log file");
+            std::fs::remove_file(path)
+                .expect("failed to remove test file");
+            line
+        };
+        // like 2026-01-31T12:34:56Z
+        let is_timestamp = |text: &str| text.len() == 20 && text.bytes().zip("dddd-dd-ddTdd:dd:ddZ".bytes())
+            .all(|(c, pattern)| if pattern == b'd' { c.is_ascii_digit() } else { c == pattern });
+        let path = "testdata/bzip2rs-timestamps.log";
+        let line = logged(&["bzip2rs", "--log-timestamps", "--log-file", path], path);
+        assert!(line.get(1..21).is_some_and(is_timestamp), "{line}");
+        let line = logged(&["bzip2rs", "-vv", "--log-file", path], path);
+        assert!(line.get(1..21).is_some_and(is_timestamp), "{line}");
+        let line = logged(&["bzip2rs", "--log-file", path], path);
+        assert!(line.starts_with("[WARN  bzip2rs] timed"), "{line}");
+    }
+
+    #[test]
+    fn test_suffix_list() {
+        let files = ["testdata/e-list1.txt.bz2", "testdata/e-list2.txt.z2", "testdata/e-list3.txt.bz"];
+        for file in files {
+            std::fs::copy("testdata/e.txt.bz2", file)
+                .expect("failed to copy test file");
+        }
+        let mut args = vec!["bzip2rs", "-d", "-q", "--suffix", ".bz2,.z2,.bz"];
+        args.extend(files);
+        assert!(do_main(args).is_ok());
+        for (file, original) in files.iter().zip(["testdata/e-list1.txt", "testdata/e-list2.txt", "testdata/e-list3.txt"]) {
+            assert!(!Path::new(file).exists());
+            assert!(Path::new(original).is_file());
+            std::fs::remove_file(original)
+                .expect("failed to remove test file");
+        }
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "-S", ".z2,.bz"]);
+        assert_eq!(".z2", cli.compressed_suffix());
+    }
+
+    #[test]
+    fn test_force_unknown_suffix() {
+        let file = "testdata/e-forced.dat";
+        assert!(unknown_name_warning(file, &cli::Bzip2Cli::parse_from(["bzip2rs", "-d", file])).is_some());
+        assert_eq!(None, unknown_name_warning(file, &cli::Bzip2Cli::parse_from(["bzip2rs", "-df", file])));
+        std::fs::copy("testdata/e.txt.bz2", file)
+            .expect("failed to copy test file");
+        assert!(do_main(vec!["bzip2rs", "-df", file]).is_ok());
+        let result = Path::new("testdata/e-forced.dat.out");
+        assert!(result.is_file());
+        std::fs::remove_file(result)
+            .expect("failed to remove test file");
+    }
+
+    #[test]
+    fn test_retries() {
+        let flaky = |failures: usize| {
+            let mut calls = 0;
+            move || {
+                calls += 1;
+                if calls <= failures {
+                    Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "timed out"))
+                } else {
+                    Ok(calls)
+                }
+            }
+        };
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "--retries", "3"]);
+        assert_eq!(3, with_retries(&cli, "flaky", flaky(2)).unwrap_or(0));
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "--retries", "1"]);
+        assert!(with_retries(&cli, "flaky", flaky(2)).is_err());
+        assert!(with_retries(&cli::Bzip2Cli::default(), "flaky", flaky(1)).is_err());
+        let mut calls = 0;
+        let r: std::io::Result<()> = with_retries(&cli, "missing", || {
+            calls += 1;
+            Err(std::io::Error::from(std::io::ErrorKind::NotFound))
+        });
+        assert!(r.is_err());
+        assert_eq!(1, calls);
+    }
+
+    #[test]
+    fn test_batch_eta() {
+        let second = Duration::from_secs(1);
+        assert_eq!(Some(Duration::from_secs(3)), eta(250, 1000, second));
+        assert_eq!(Some(Duration::ZERO), eta(1000, 1000, second));
+        assert_eq!(None, eta(0, 1000, second));
+        assert_eq!(None, eta(250, 1000, Duration::ZERO));
+
+        let mut batch = BatchProgress::new(&cli::Bzip2Cli::default(), "bzip2rs");
+        (batch.files, batch.total) = (4, 1000);
+        batch.file_done(250);
+        assert_eq!("1/4 files, 25% done, about 3s left", batch.line(second));
+    }
+
+    #[test]
+    fn test_no_preserve() {
+        let old = std::time::SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let modified = |file: &str| std::fs::metadata(file).and_then(|m| m.modified())
+            .expect("failed to read modification time");
+        for (args, file, preserved) in [(&["bzip2rs", "-q", "-k"][..], "testdata/alice-preserve1.txt", true), (&["bzip2rs", "-q", "-k", "--no-preserve=time"], "testdata/alice-preserve2.txt", false)] {
+            std::fs::copy("testdata/alice-in-wonderland.txt", file)
+                .expect("failed to copy test file");
+            std::fs::File::options().write(true).open(file)
+                .and_then(|f| f.set_modified(old))
+                .expect("failed to set modification time");
+            let mut args = args.to_vec();
+            args.push(file);
+            assert!(do_main(args).is_ok());
+            let dest = format!("{file}.bz2");
+            assert_eq!(preserved, modified(&dest) == old);
+            for file in [file, &dest] {
+                std::fs::remove_file(file)
+                    .expect("failed to remove test file");
+            }
+        }
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "--no-preserve"]);
+        assert!(!cli.preserves(cli::Preserve::Mode) && !cli.preserves(cli::Preserve::Time) && !cli.preserves(cli::Preserve::Owner));
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "--no-preserve=mode,owner", "file"]);
+        assert!(!cli.preserves(cli::Preserve::Mode) && cli.preserves(cli::Preserve::Time) && !cli.preserves(cli::Preserve::Owner));
+        assert_eq!(vec!["file".to_string()], cli.input_files);
+        assert!(cli::Bzip2Cli::default().preserves(cli::Preserve::Time));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_preserve_owner() {
+        use std::os::unix::fs::MetadataExt;
+
+        if !is_root() {
+            return;
+        }
+        let file = "testdata/alice-owner.txt";
+        std::fs::copy("testdata/alice-in-wonderland.txt", file)
+            .expect("failed to copy test file");
+        std::os::unix::fs::chown(file, Some(4321), Some(4322))
+            .expect("failed to change owner");
+        assert!(do_main(vec!["bzip2rs", "-q", "-k", file]).is_ok());
+        let metadata = std::fs::metadata("testdata/alice-owner.txt.bz2")
+            .expect("failed to read metadata");
+        assert_eq!((4321, 4322), (metadata.uid(), metadata.gid()));
+        for file in [file, "testdata/alice-owner.txt.bz2"] {
+            std::fs::remove_file(file)
+                .expect("failed to remove test file");
+        }
+    }
+
+    #[test]
+    fn test_auto_threads() {
+        assert!(cli::Bzip2Cli::parse_from(["bzip2rs", "--threads", "0"]).resolved_threads() >= 1);
+        assert_eq!(3, cli::Bzip2Cli::parse_from(["bzip2rs", "--threads", "3"]).resolved_threads());
+    }
+
+    #[test]
+    fn test_count_bad_blocks() {
+        let data = std::fs::read("testdata/alice-in-wonderland.txt")
+            .expect("failed to read test file")
+            .repeat(3);
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "-b", "1"]);
+        let mut compressed = compress_to_vec(&data, &cli)
+            .expect("failed to compress");
+        let starts = bzip2::block_starts(&compressed);
+        let blocks = starts.len();
+        assert!(blocks > 3);
+        let tally = bzip2::check_blocks(compressed.as_slice(), &cli).expect("failed to read compressed data");
+        assert_eq!(bzip2::BlockTally { blocks, bad: 0, bytes: data.len() as u64 }, tally);
+
+        for block in [1, 3] {
+            compressed[starts[block] / 8 + 1000] ^= 0x55;
+        }
+        let tally = bzip2::check_blocks(compressed.as_slice(), &cli).expect("failed to read compressed data");
+        assert_eq!((blocks, 2), (tally.blocks, tally.bad));
+
+        let file = "testdata/alice-bad-blocks.bz2";
+        std::fs::write(file, &compressed)
+            .expect("failed to write test file");
+        let r = do_main(vec!["bzip2rs", "-q", "-t", "--continue", file]);
+        assert!(r.is_err_and(|e| e.to_string().contains(&format!("2 of {blocks} block(s) bad"))));
+        std::fs::remove_file(file)
+            .expect("failed to remove test file");
+    }
+
+    #[test]
+    fn test_output_dash_writes_to_stdout() {
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "-o", "-", "file"]);
+        assert!(cli.is_stdout("bzip2rs"));
+        assert!(cli.keep("bzip2rs"));
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "-o", "out.bz2", "file"]);
+        assert!(!cli.is_stdout("bzip2rs"));
+        assert_eq!(cli.output_path("file.bz2"), "out.bz2");
+        assert!(cli.keep("bzip2rs"));
+    }
+
+    #[test]
+    fn test_output_path() {
+        let file = "testdata/output-path.txt";
+        let out = "testdata/output-path.out.bz2";
+        std::fs::copy("testdata/alice-in-wonderland.txt", file).expect("failed to copy test file");
+        assert!(do_main(vec!["bzip2rs", "-q", "-o", out, file]).is_ok());
+        assert!(std::path::Path::new(file).exists());
+        assert!(do_main(vec!["bzip2rs", "-q", "-t", out]).is_ok());
+        assert!(do_main(vec!["bzip2rs", "-q", "-o", out, file, file]).is_err());
+        std::fs::remove_file(file).expect("failed to remove test file");
+        std::fs::remove_file(out).expect("failed to remove test file");
+    }
+
+    #[test]
+    fn test_list_json() {
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "-l", "--json"]);
+        assert!(cli.json);
+        let file = "testdata/e.txt.bz2";
+        let info = list_each(file, &cli).expect("failed to list test file");
+        let json = format_list_json(&[(file.to_string(), info)], None);
+        let field = |name: &str| json.split(&format!("\"{name}\": ")).nth(1)
+            .and_then(|rest| rest.split([',', '}']).next())
+            .map(str::to_string);
+        let data = std::fs::read(file).expect("failed to read test file");
+        let expected = decompress_to_vec(&data).expect("failed to decompress test file").len();
+        assert_eq!(Some(expected.to_string()), field("uncompressed"));
+        assert_eq!(Some(format!("\"{file}\"")), field("name"));
+        assert_eq!(Some("900000".to_string()), field("block_size"));
+        assert!(json.starts_with("[\n") && json.ends_with("\n]\n"));
+        assert_eq!(None, field("seconds"));
+        assert_eq!("[]\n", format_list_json(&[], None));
+        let timed = format_list_json(&[(file.to_string(), info)], Some(&[Duration::from_millis(1500)]));
+        assert!(timed.contains("\"streams\": 1, \"seconds\": 1.500}"));
+        assert_eq!(r#""a\"b\\c\n""#, json_string("a\"b\\c\n"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_compress_mmap() {
+        let data = std::fs::read("testdata/alice-in-wonderland.txt")
+            .expect("failed to read test file");
+        let large = data.repeat(8);
+        let files = ["testdata/alice-mmap.txt", "testdata/alice-read.txt"];
+        for file in files {
+            std::fs::write(file, &large).expect("failed to write test file");
+        }
+        assert!(do_main(vec!["bzip2rs", "-q", "--mmap", files[0]]).is_ok());
+        assert!(do_main(vec!["bzip2rs", "-q", files[1]]).is_ok());
+        let mapped = std::fs::read("testdata/alice-mmap.txt.bz2").expect("failed to read test file");
+        let read = std::fs::read("testdata/alice-read.txt.bz2").expect("failed to read test file");
+        assert_eq!(read, mapped);
+        assert_eq!(large, decompress_to_vec(&mapped).expect("failed to decompress"));
+        for file in ["testdata/alice-mmap.txt.bz2", "testdata/alice-read.txt.bz2"] {
+            std::fs::remove_file(file).expect("failed to remove test file");
+        }
+    }
+
+    #[test]
+    fn test_output_same_as_input() {
+        let file = "testdata/e-same.txt.bz2";
+        std::fs::copy("testdata/e.txt.bz2", file).expect("failed to copy test file");
+        let original = std::fs::read(file).expect("failed to read test file");
+        let r = do_main(vec!["bzip2rs", "-q", "-d", "-f", "-o", file, file]);
+        assert!(matches!(r, Err(Error::InvalidInput(_))));
+        assert_eq!(original, std::fs::read(file).expect("failed to read test file"));
+        std::fs::remove_file(file).expect("failed to remove test file");
+
+        let file = "testdata/e-same.txt";
+        std::fs::write(file, b"bzip2rs").expect("failed to write test file");
+        let r = do_main(vec!["bzip2rs", "-q", "-f", "-o", "testdata/./e-same.txt", file]);
+        assert!(matches!(r, Err(Error::InvalidInput(_))));
+        assert_eq!(b"bzip2rs".to_vec(), std::fs::read(file).expect("failed to read test file"));
+        std::fs::remove_file(file).expect("failed to remove test file");
+    }
+
+    #[test]
+    fn test_queued_line() {
+        let dir = "testdata/queued";
+        std::fs::create_dir_all(format!("{dir}/sub")).expect("failed to create test dir");
+        for file in ["a.txt", "b.txt", "sub/c.txt"] {
+            std::fs::write(format!("{dir}/{file}"), b"bzip2rs").expect("failed to write test file");
+        }
+        let mut cli = cli::Bzip2Cli::parse_from(["bzip2rs", "-r", dir]);
+        cli.input_files = walk::expand_inputs(&cli, &mut vec![]).expect("failed to expand inputs");
+        assert_eq!("queued 3 files to compress", queued_line(&cli, "compress"));
+        assert_eq!("3/3", position(2, &cli));
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "-d", "file.bz2"]);
+        assert_eq!("queued 1 file to decompress", queued_line(&cli, "decompress"));
+        std::fs::remove_dir_all(dir).expect("failed to remove test dir");
+    }
+
+    #[test]
+    fn test_report_to_stdout() {
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "-tc", "testdata/e.txt.bz2"]);
+        assert!(matches!(cli.mode("bzip2rs"), cli::Mode::Test));
+        let mut report = vec![];
+        for (file, (result, _)) in cli.iter().zip(test_files(&cli)) {
+            write_report(&mut report, file, result.is_ok());
+        }
+        write_report(&mut report, "testdata/broken.bz2", false);
+        assert_eq!("OK\ttestdata/e.txt.bz2\nFAIL\ttestdata/broken.bz2\n", String::from_utf8(report).expect("output is not UTF-8"));
+    }
+
+    #[test]
+    fn test_auto_level() {
+        assert_eq!(1, cli::Bzip2Cli::auto_level(0));
+        assert_eq!(1, cli::Bzip2Cli::auto_level(50_000));
+        assert_eq!(2, cli::Bzip2Cli::auto_level(100_001));
+        assert_eq!(9, cli::Bzip2Cli::auto_level(900_000));
+        assert_eq!(9, cli::Bzip2Cli::auto_level(10_000_000));
+
+        let file = "testdata/auto-level.txt";
+        std::fs::write(file, vec![b'a'; 50_000]).expect("failed to write test file");
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "--auto-level", "--best", file]);
+        let input = std::fs::File::open(file).expect("failed to open test file");
+        assert_eq!(1, auto_level_cli(file, &input, &cli).expect("no level picked").compress_level());
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "--best", file]);
+        assert!(auto_level_cli(file, &input, &cli).is_none());
+        std::fs::remove_file(file).expect("failed to remove test file");
+    }
+
+    #[test]
+    fn test_prepend_roundtrip() {
+        let prefix = "testdata/prepend-prefix.txt";
+        let file = "testdata/prepend.txt";
+        std::fs::write(prefix, b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n").expect("failed to write test file");
+        std::fs::write(file, b"<note>bzip2rs</note>\n").expect("failed to write test file");
+        assert!(do_main(vec!["bzip2rs", "-q", "--prepend", prefix, file]).is_ok());
+        let compressed = std::fs::read("testdata/prepend.txt.bz2").expect("failed to read test file");
+        // plain decompression restores the header along with the input.
+        assert!(decompress_to_vec(&compressed).expect("failed to decompress").ends_with(b"<note>bzip2rs</note>\n"));
+        assert!(do_main(vec!["bzip2rs", "-q", "-d", "-k", "--prepend", file, "testdata/prepend.txt.bz2"]).is_err());
+        assert!(do_main(vec!["bzip2rs", "-q", "-d", "-f", "--prepend", prefix, "testdata/prepend.txt.bz2"]).is_ok());
+        assert_eq!(b"<note>bzip2rs</note>\n".to_vec(), std::fs::read(file).expect("failed to read test file"));
+        for file in [prefix, file] {
+            std::fs::remove_file(file).expect("failed to remove test file");
+        }
+    }
+
+    #[test]
+    fn test_keeps_tested_files() {
+        let keep = |args: &[&str], program_name: &str| cli::Bzip2Cli::parse_from(args).keep(program_name);
+        assert!(keep(&["bzip2rs", "-t", "file.bz2"], "bzip2rs"));
+        assert!(keep(&["bzip2rs", "-d", "-t", "file.bz2"], "bzip2rs"));
+        assert!(keep(&["bunzip2", "-t", "file.bz2"], "bunzip2"));
+        assert!(keep(&["bzip2rs", "-l", "file.bz2"], "bzip2rs"));
+        assert!(!keep(&["bzip2rs", "-d", "file.bz2"], "bzip2rs"));
+        let file = "testdata/e-kept.txt.bz2";
+        std::fs::copy("testdata/e.txt.bz2", file).expect("failed to copy test file");
+        assert!(do_main(vec!["bzip2rs", "-q", "-t", file]).is_ok());
+        assert!(Path::new(file).exists());
+        assert!(do_main(vec!["bzip2rs", "-q", "-d", "-t", file]).is_ok());
+        assert!(Path::new(file).exists());
+        assert!(!Path::new("testdata/e-kept.txt").exists());
+        std::fs::remove_file(file).expect("failed to remove test file");
+    }
+
+    #[test]
+    fn test_files_after_double_dash() {
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "-k", "--", "-t", "--keep"]);
+        assert_eq!(vec!["-t", "--keep"], cli.input_files);
+        assert_eq!(0, cli.test);
+        assert!(matches!(cli.mode("bzip2rs"), cli::Mode::Compress));
+
+        let file = "-t";
+        std::fs::write(file, b"bzip2rs").expect("failed to write test file");
+        assert!(do_main(vec!["bzip2rs", "-q", "--", file]).is_ok());
+        assert!(!Path::new(file).exists());
+        assert!(do_main(vec!["bzip2rs", "-q", "-d", "--", "-t.bz2"]).is_ok());
+        assert_eq!(b"bzip2rs".to_vec(), std::fs::read(file).expect("failed to read test file"));
+        std::fs::remove_file(file).expect("failed to remove test file");
+    }
+
+    #[test]
+    fn test_glob_inputs() {
+        let dir = "testdata/glob";
+        std::fs::create_dir_all(dir).expect("failed to create test dir");
+        for file in ["a.txt", "b.txt", "c.dat"] {
+            std::fs::write(format!("{dir}/{file}"), b"bzip2rs").expect("failed to write test file");
+        }
+        let expand = |args: &[&str]| walk::expand_inputs(&cli::Bzip2Cli::parse_from(args), &mut vec![]);
+        let txt = ["a.txt", "b.txt"].map(|file| Path::new(dir).join(file).to_string_lossy().into_owned()).to_vec();
+        assert_eq!(txt, expand(&["bzip2rs", "--glob", "testdata/glob/*.txt"]).expect("failed to expand inputs"));
+        assert!(matches!(expand(&["bzip2rs", "--glob", "testdata/glob/*.bz2"]), Err(Error::InvalidInput(_))));
+        #[cfg(windows)]
+        assert_eq!(txt, expand(&["bzip2rs", "testdata/glob/*.txt"]).expect("failed to expand inputs"));
+        #[cfg(not(windows))]
+        assert_eq!(vec!["testdata/glob/*.txt"], expand(&["bzip2rs", "testdata/glob/*.txt"]).expect("failed to expand inputs"));
+        std::fs::remove_dir_all(dir).expect("failed to remove test dir");
+    }
+
+    #[test]
+    fn test_checksum_trailer() {
+        let file = "testdata/alice-checksum.txt";
+        std::fs::copy("testdata/alice-in-wonderland.txt", file).expect("failed to copy test file");
+        assert!(do_main(vec!["bzip2rs", "-q", "-k", "-N", "--checksum-trailer", file]).is_ok());
+        let compressed = "testdata/alice-checksum.txt.bz2";
+        let mut data = std::fs::read(compressed).expect("failed to read test file");
+        let original = std::fs::read(file).expect("failed to read test file");
+        let name_len = trailer::trailer_len(&data).expect("no trailer found") - 14;
+        let crc_at = data.len() - name_len - 9;
+        assert_eq!(crc32fast::hash(&original).to_le_bytes(), data[crc_at..crc_at + 4]);
+        assert_eq!(Some(crc32fast::hash(&original)), trailer::read_checksum(std::io::Cursor::new(&data)).expect("failed to read checksum trailer"));
+        assert!(do_main(vec!["bzip2rs", "-q", "-t", compressed]).is_ok());
+        assert!(do_main(vec!["bzip2rs", "-q", "-d", "-f", compressed]).is_ok());
+        assert_eq!(original, std::fs::read(file).expect("failed to read test file"));
+
+        data[crc_at] ^= 1;
+        std::fs::write(compressed, &data).expect("failed to write test file");
+        assert!(matches!(do_main(vec!["bzip2rs", "-q", "-t", compressed]), Err(Error::CorruptData(_))));
+        assert!(do_main(vec!["bzip2rs", "-q", "-d", "-f", "-k", compressed]).is_err());
+        // the output failing the check is removed.
+        assert!(!Path::new(file).exists());
+        assert_eq!(None, trailer::read_checksum(std::fs::File::open("testdata/e.txt.bz2").expect("failed to open test file")).expect("failed to read checksum trailer"));
+        std::fs::remove_file(compressed).expect("failed to remove test file");
+    }
+
+    #[test]
+    fn test_limit_rate() {
+        let data = vec![b'a'; 30_000];
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "--limit-rate", "10000"]);
+        let start = Instant::now();
+        let compressed = compress_to_vec(&data, &cli).expect("failed to compress");
+        // the bucket starts with a second's worth of bytes, the rest takes two seconds.
+        assert!(start.elapsed() >= Duration::from_millis(1900));
+        let start = Instant::now();
+        let mut decompressed = vec![];
+        bzip2::decompress(compressed.as_slice(), &mut decompressed, &cli).expect("failed to decompress");
+        assert!(start.elapsed() >= Duration::from_millis(1900));
+        assert_eq!(data, decompressed);
+    }
+
+    #[test]
+    fn test_compression_options_from_cli() {
+        assert_eq!(CompressionOptions::default(), CompressionOptions::from(&cli::Bzip2Cli::default()));
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "--best", "-s", "--workfactor", "100", "--buffer-size", "8", "--threads", "3", "--limit-rate", "1000"]);
+        let expected = CompressionOptions::default().level(9).small(true).workfactor(100).buffer_size(8 * 1024).threads(3).limit_rate(1000);
+        assert_eq!(expected, CompressionOptions::from(&cli));
+        assert_eq!(1, CompressionOptions::from(&cli::Bzip2Cli::parse_from(["bzip2rs", "--fast"])).level);
+        assert_eq!(9, CompressionOptions::default().level(20).level);
+    }
+
+    #[test]
+    fn test_decompress_stream_count() {
+        let data = std::fs::read("testdata/e.txt.bz2").expect("failed to read test file");
+        let concatenated = [data.as_slice(), data.as_slice()].concat();
+        let mut decompressed = vec![];
+        let stats = bzip2::decompress(concatenated.as_slice(), &mut decompressed, &cli::Bzip2Cli::default())
+            .expect("failed to decompress");
+        assert_eq!(2, stats.streams);
+        assert_eq!(decompressed.len() as u64, stats.written);
+        let stats = bzip2::decompress(data.as_slice(), std::io::sink(), &cli::Bzip2Cli::default())
+            .expect("failed to decompress");
+        assert_eq!(1, stats.streams);
+    }
+
+    #[test]
+    fn test_no_delete() {
+        let deletes = |args: &[&str], safe: bool| cli::Bzip2Cli::parse_from(args).deletes("bzip2rs", safe);
+        assert!(deletes(&["bzip2rs", "file"], false));
+        assert!(!deletes(&["bzip2rs", "--no-delete", "file"], false));
+        assert!(!deletes(&["bzip2rs", "file"], true));
+        assert!(deletes(&["bzip2rs", "--delete", "file"], true));
+        assert!(!deletes(&["bzip2rs", "--delete", "-c", "file"], true));
+        assert!(cli::Bzip2Cli::try_parse_from(["bzip2rs", "--delete", "-k", "file"]).is_err());
+
+        let file = "testdata/no-delete.txt";
+        std::fs::write(file, b"bzip2rs").expect("failed to write test file");
+        assert!(do_main(vec!["bzip2rs", "-q", "--no-delete", file]).is_ok());
+        assert!(Path::new(file).exists());
+        assert!(Path::new("testdata/no-delete.txt.bz2").exists());
+        for file in [file, "testdata/no-delete.txt.bz2"] {
+            std::fs::remove_file(file).expect("failed to remove test file");
+        }
+    }
+
+    #[test]
+    fn test_ratio_threshold() {
+        let file = "testdata/random-threshold.bin";
+        let data = pseudo_random_bytes(64 * 1024);
+        std::fs::write(file, &data).expect("failed to write test file");
+        assert!(do_main(vec!["bzip2rs", "-q", "--ratio-threshold", "0.95", file]).is_ok());
+        assert!(!Path::new("testdata/random-threshold.bin.bz2").exists());
+        assert_eq!(data, std::fs::read(file).expect("failed to read test file"));
+
+        let text = "testdata/alice-threshold.txt";
+        std::fs::copy("testdata/alice-in-wonderland.txt", text).expect("failed to copy test file");
+        assert!(do_main(vec!["bzip2rs", "-q", "--ratio-threshold", "0.95", text]).is_ok());
+        assert!(!Path::new(text).exists());
+        for file in [file, "testdata/alice-threshold.txt.bz2"] {
+            std::fs::remove_file(file).expect("failed to remove test file");
+        }
+    }
+
+    #[test]
+    fn test_crc_summary() {
+        let data = std::fs::read("testdata/alice-in-wonderland.txt").expect("failed to read test file");
+        let compressed = compress_to_vec(&data, CompressionOptions::default().level(1)).expect("failed to compress");
+        let stats = bzip2::decompress(compressed.as_slice(), std::io::sink(), &cli::Bzip2Cli::default())
+            .expect("failed to decompress");
+        let blocks = data.len().div_ceil(100_000);
+        #[cfg(not(feature = "sys"))]
+        assert_eq!(format!("{blocks} block CRC(s) verified, {blocks} combined stream CRC(s) matched"), stats.crc_summary());
+        #[cfg(feature = "sys")]
+        assert_eq!("1 stream(s) decoded with all CRCs matching", stats.crc_summary());
+        assert!(blocks > 1);
+    }
+
+    #[test]
+    fn test_skip_compressed() {
+        std::fs::copy("testdata/e.txt.bz2", "testdata/skip_compressed.dat").expect("failed to copy test file");
+        std::fs::copy("testdata/alice-in-wonderland.txt", "testdata/skip_compressed.txt").expect("failed to copy test file");
+        assert_eq!(Some("bzip2"), suffix::compressed_format("testdata/skip_compressed.dat"));
+        assert_eq!(None, suffix::compressed_format("testdata/skip_compressed.txt"));
+        let r = do_main(vec!["bzip2rs", "-q", "-k", "--skip-compressed", "testdata/skip_compressed.dat", "testdata/skip_compressed.txt"]);
+        assert!(r.is_ok());
+        assert!(!Path::new("testdata/skip_compressed.dat.bz2").exists());
+        assert!(Path::new("testdata/skip_compressed.txt.bz2").exists());
+        for file in ["testdata/skip_compressed.dat", "testdata/skip_compressed.txt", "testdata/skip_compressed.txt.bz2"] {
+            std::fs::remove_file(file).expect("failed to remove test file");
+        }
+    }
+
+    #[test]
+    fn test_batch_outcome() {
+        std::fs::copy("testdata/alice-in-wonderland.txt", "testdata/batch_outcome1.txt").expect("failed to copy test file");
+        std::fs::copy("testdata/alice-in-wonderland.txt", "testdata/batch_outcome2.txt").expect("failed to copy test file");
+        std::fs::write("testdata/batch_outcome2.txt.bz2", b"existing").expect("failed to write test file");
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "-q", "-k", "testdata/batch_outcome1.txt", "testdata/batch_outcome2.txt", "testdata/batch_outcome3.txt"]);
+        let outcome = perform_compress(&cli, "bzip2rs", &mut vec![]).expect("failed to compress");
+        assert_eq!(1, outcome.ok);
+        assert_eq!(2, outcome.failed.len());
+        assert!(matches!(outcome.failed[0], Error::FileExists(_)));
+        assert!(outcome.into_result().is_err());
+        for file in ["testdata/batch_outcome1.txt", "testdata/batch_outcome1.txt.bz2", "testdata/batch_outcome2.txt", "testdata/batch_outcome2.txt.bz2"] {
+            std::fs::remove_file(file).expect("failed to remove test file");
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "sys"))]
+    fn test_flush_interval() {
+        use std::sync::mpsc::Receiver;
+        use std::sync::{Arc, Mutex};
+
+        /// Reads the chunks of a channel, blocking between them like a slow log.
+        struct Trickle(Receiver<Vec<u8>>);
+        impl std::io::Read for Trickle {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let Ok(chunk) = self.0.recv() else {
+                    return Ok(0);
+                };
+                buf[..chunk.len()].copy_from_slice(&chunk);
+                Ok(chunk.len())
+            }
+        }
+        struct Shared(Arc<Mutex<Vec<u8>>>);
+        impl Write for Shared {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().expect("failed to lock output").extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let output = Arc::new(Mutex::new(vec![]));
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let options = CompressionOptions::default().flush_interval(Duration::from_millis(50));
+        let writer = Shared(output.clone());
+        let compressing = std::thread::spawn(move || bzip2::compress(Trickle(receiver), writer, &options));
+        sender.send(b"first line\n".to_vec()).expect("failed to send chunk");
+        std::thread::sleep(Duration::from_millis(300));
+        // nothing was written since the first line, yet its block is out.
+        assert!(output.lock().expect("failed to lock output").starts_with(b"BZh6"));
+        sender.send(b"second line\n".to_vec()).expect("failed to send chunk");
+        drop(sender);
+        let stats = compressing.join().expect("compression thread panicked").expect("failed to compress");
+        assert_eq!(2, stats.streams);
+        let compressed = output.lock().expect("failed to lock output").clone();
+        assert_eq!(b"first line\nsecond line\n".to_vec(), decompress_to_vec(&compressed).expect("failed to decompress"));
+        assert!(cli::Bzip2Cli::try_parse_from(["bzip2rs", "--flush-interval", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_corrupt_offset() {
+        let data = std::fs::read("testdata/alice_in_wonderland.txt.bz2").expect("failed to read test file");
+        let truncated = &data[..10_000];
+        let r = bzip2::test_integrity(truncated, &cli::Bzip2Cli::default());
+        let Err(Error::CorruptData(msg)) = r else {
+            panic!("truncated data is not reported as corrupt: {r:?}");
+        };
+        let offset = msg.rsplit("corrupt at offset ").next().and_then(|n| n.split(' ').next()?.parse::<u64>().ok()).expect("no offset in the message");
+        assert!((9_000..=10_000).contains(&offset), "{msg}");
+
+        // a byte changed within the second of several blocks.
+        let data = std::fs::read("testdata/alice-in-wonderland.txt").expect("failed to read test file").repeat(3);
+        let mut compressed = compress_to_vec(&data, CompressionOptions::default().level(1)).expect("failed to compress");
+        let starts = bzip2::block_starts(&compressed).iter().map(|bit| (bit / 8) as u64).collect::<Vec<_>>();
+        assert!(starts.len() > 3);
+        compressed[starts[1] as usize + 1000] ^= 0x55;
+        let r = bzip2::test_integrity(compressed.as_slice(), &cli::Bzip2Cli::default());
+        let Err(Error::CorruptData(msg)) = r else {
+            panic!("changed data is not reported as corrupt: {r:?}");
+        };
+        let number_after = |prefix: &str| msg.split(prefix).nth(1).and_then(|n| n.split(' ').next()?.parse::<u64>().ok());
+        assert_eq!(Some(starts[1]), number_after("in the block at offset "), "{msg}");
+        let offset = number_after("corrupt at offset ").expect("no offset in the message");
+        assert!((starts[1]..starts[2] + 1024).contains(&offset), "{msg}");
+    }
+
+    #[test]
+    fn test_version_backend() {
+        let version = cli::Bzip2Cli::parse_from(["bzip2rs", "-V"]).banner().expect("no banner for -V");
+        #[cfg(feature = "sys")]
+        assert!(version.contains("Backend: libbzip2 1."), "{version}");
+        #[cfg(not(feature = "sys"))]
+        assert!(version.contains("Backend: pure Rust"), "{version}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_read_descriptor_path() {
+        use std::os::fd::AsRawFd;
+        // the pipe of a child process, like `<(cat FILE)` of bash.
+        let file = "testdata/alice-in-wonderland.txt";
+        let mut child = std::process::Command::new("cat").arg(file)
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .expect("failed to run cat");
+        let input = format!("/dev/fd/{}", child.stdout.as_ref().expect("no stdout of child").as_raw_fd());
+        assert!(is_descriptor_path(&input));
+        assert!(is_descriptor_path("/proc/self/fd/3") && is_descriptor_path("/dev/stdin"));
+        assert!(!is_descriptor_path("testdata/fd/3"));
+        let output = "testdata/descriptor-input.bz2";
+        assert!(do_main(vec!["bzip2rs", "-q", "-o", output, &input]).is_ok());
+        assert!(child.wait().is_ok_and(|status| status.success()));
+        let compressed = std::fs::read(output).expect("failed to read output");
+        assert_eq!(std::fs::read(file).expect("failed to read test file"), decompress_to_vec(&compressed).expect("failed to decompress"));
+        std::fs::remove_file(output).expect("failed to remove test file");
+    }
+
+    #[test]
+    fn test_auto_mode() {
+        std::fs::copy("testdata/alice-in-wonderland.txt", "testdata/auto-plain.txt").expect("failed to copy test file");
+        std::fs::copy("testdata/e.txt.bz2", "testdata/auto-compressed.txt.bz2").expect("failed to copy test file");
+        assert!(do_main(vec!["bzip2rs", "-q", "--auto", "testdata/auto-plain.txt", "testdata/auto-compressed.txt.bz2"]).is_ok());
+        assert!(!Path::new("testdata/auto-plain.txt").exists());
+        assert!(!Path::new("testdata/auto-compressed.txt.bz2").exists());
+        let compressed = std::fs::read("testdata/auto-plain.txt.bz2").expect("failed to read test file");
+        assert_eq!(std::fs::read("testdata/alice-in-wonderland.txt").expect("failed to read test file"), decompress_to_vec(&compressed).expect("failed to decompress"));
+        let decompressed = std::fs::read("testdata/auto-compressed.txt").expect("failed to read test file");
+        assert_eq!(decompress_to_vec(&std::fs::read("testdata/e.txt.bz2").expect("failed to read test file")).expect("failed to decompress"), decompressed);
+        for file in ["testdata/auto-plain.txt.bz2", "testdata/auto-compressed.txt"] {
+            std::fs::remove_file(file).expect("failed to remove test file");
+        }
+    }
+
+    #[test]
+    fn test_temp_dir() {
+        let dir = "testdata/temp-dir-staging";
+        std::fs::create_dir_all(dir).expect("failed to create test directory");
+        std::fs::copy("testdata/alice-in-wonderland.txt", "testdata/temp-dir.txt").expect("failed to copy test file");
+        let option = format!("--temp-dir={dir}");
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", &option]);
+        assert_eq!(Some(Path::new(dir).to_path_buf()), cli.temp_dir());
+        assert!(staging_path("testdata/temp-dir.txt.bz2", &cli).starts_with("testdata/temp-dir-staging/temp-dir.txt.bz2."));
+        assert_eq!(Some(std::env::temp_dir()), cli::Bzip2Cli::parse_from(["bzip2rs", "--temp-dir"]).temp_dir());
+
+        assert!(do_main(vec!["bzip2rs", "-q", &option, "testdata/temp-dir.txt"]).is_ok());
+        assert!(Path::new("testdata/temp-dir.txt.bz2").exists());
+        assert!(do_main(vec!["bzip2rs", "-q", "-d", &option, "testdata/temp-dir.txt.bz2"]).is_ok());
+        assert_eq!(std::fs::read("testdata/alice-in-wonderland.txt").expect("failed to read test file"), std::fs::read("testdata/temp-dir.txt").expect("failed to read test file"));
+        assert_eq!(0, std::fs::read_dir(dir).expect("failed to read test directory").count());
+
+        // the fallback for another file system.
+        std::fs::write("testdata/temp-dir-staging/copied.part", b"staged").expect("failed to write test file");
+        copy_into_place("testdata/temp-dir.txt", "testdata/temp-dir-staging/copied.part", "testdata/temp-dir-copied", &cli).expect("failed to copy into place");
+        assert_eq!(b"staged".to_vec(), std::fs::read("testdata/temp-dir-copied").expect("failed to read test file"));
+        assert!(!Path::new("testdata/temp-dir-staging/copied.part").exists());
+        for file in ["testdata/temp-dir.txt", "testdata/temp-dir-copied"] {
+            std::fs::remove_file(file).expect("failed to remove test file");
+        }
+        std::fs::remove_dir(dir).expect("failed to remove test directory");
+    }
+
+    #[test]
+    fn test_double_suffix_strips_one_layer() {
+        let data = std::fs::read("testdata/alice-in-wonderland.txt").expect("failed to read test file");
+        let once = compress_to_vec(&data, CompressionOptions::default()).expect("failed to compress");
+        let twice = compress_to_vec(&once, CompressionOptions::default()).expect("failed to compress");
+        std::fs::write("testdata/double.txt.bz2.bz2", twice).expect("failed to write test file");
+        assert_eq!(Some("testdata/double.txt.bz2".to_string()), suffix::original_name("testdata/double.txt.bz2.bz2", &[]));
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "testdata/double.txt.bz2.bz2"]);
+        assert!(matches!(cli.mode("bzip2rs"), cli::Mode::Decompress));
+
+        assert!(do_main(vec!["bzip2rs", "-q", "testdata/double.txt.bz2.bz2"]).is_ok());
+        assert_eq!(once, std::fs::read("testdata/double.txt.bz2").expect("failed to read test file"));
+        assert!(!Path::new("testdata/double.txt").exists());
+        assert!(do_main(vec!["bzip2rs", "-q", "testdata/double.txt.bz2"]).is_ok());
+        assert_eq!(data, std::fs::read("testdata/double.txt").expect("failed to read test file"));
+        assert!(!Path::new("testdata/double.txt.bz2").exists());
+        std::fs::remove_file("testdata/double.txt").expect("failed to remove test file");
+    }
+
+    #[test]
+    fn test_quiet_twice() {
+        let r = do_main(vec!["bzip2rs", "-qq", "-t", "testdata/no-such-file.bz2"]);
+        assert!(r.as_ref().is_err_and(|e| e.is_suppressed() && e.exit_code() == 1));
+        let r = do_main(vec!["bzip2rs", "-q", "-t", "testdata/no-such-file.bz2"]);
+        assert!(r.is_err_and(|e| !e.is_suppressed()));
+
+        let corrupt = "testdata/quiet-twice.bz2";
+        std::fs::write(corrupt, b"BZh9 not really").expect("failed to write test file");
+        let r = do_main(vec!["bzip2rs", "-qq", "-t", corrupt]);
+        assert!(r.is_err_and(|e| e.is_suppressed() && e.exit_code() == 2));
+        std::fs::remove_file(corrupt).expect("failed to remove test file");
+    }
+
+    #[test]
+    fn test_output_template() {
+        assert_eq!("docs/notes.txt.compressed.bz2", template::render("{dir}/{name}.compressed.bz2", "docs/notes.txt"));
+        assert_eq!("./notes-txt.{bz2}", template::render("{dir}/{stem}-{ext}.{{bz2}}", "notes.txt"));
+        assert_eq!("README.", template::render("{stem}.{ext}", "README"));
+        for bad in ["{nam}.bz2", "{name.bz2", "name}.bz2"] {
+            assert!(cli::Bzip2Cli::try_parse_from(["bzip2rs", "--output-template", bad]).is_err(), "{bad}");
+        }
+
+        let files = ["testdata/template-a.txt", "testdata/template-b.txt"];
+        for file in files {
+            std::fs::copy("testdata/alice-in-wonderland.txt", file).expect("failed to copy test file");
+        }
+        assert!(do_main(vec!["bzip2rs", "-q", "-k", "--output-template", "{dir}/{stem}.compressed.bz2", files[0], files[1]]).is_ok());
+        for stem in ["testdata/template-a", "testdata/template-b"] {
+            let compressed = std::fs::read(format!("{stem}.compressed.bz2")).expect("failed to read test file");
+            assert_eq!(std::fs::read("testdata/alice-in-wonderland.txt").expect("failed to read test file"), decompress_to_vec(&compressed).expect("failed to decompress"));
+        }
+        assert!(do_main(vec!["bzip2rs", "-q", "-d", "--output-template", "{dir}/{stem}.restored", "testdata/template-a.compressed.bz2"]).is_ok());
+        assert!(Path::new("testdata/template-a.compressed.restored").exists());
+        for file in files.into_iter().chain(["testdata/template-a.compressed.restored", "testdata/template-b.compressed.bz2"]) {
+            std::fs::remove_file(file).expect("failed to remove test file");
+        }
+    }
+
+    #[test]
+    fn test_decompress_to_file_and_pipe() {
+        let compressed = std::fs::read("testdata/alice_in_wonderland.txt.bz2").expect("failed to read test file");
+        let expected = decompress_to_vec(&compressed).expect("failed to decompress");
+
+        let output = "testdata/decompress-to-file.txt";
+        let file = std::fs::File::create(output).expect("failed to create test file");
+        assert!(bzip2::decompress(compressed.as_slice(), &file, &cli::Bzip2Cli::default()).is_ok());
+        assert_eq!(expected, std::fs::read(output).expect("failed to read test file"));
+        std::fs::remove_file(output).expect("failed to remove test file");
+
+        let (mut reader, writer) = std::io::pipe().expect("failed to create pipe");
+        let decompressing = std::thread::spawn(move || bzip2::decompress(compressed.as_slice(), writer, &cli::Bzip2Cli::default()).map(|stats| stats.written));
+        let mut piped = vec![];
+        std::io::Read::read_to_end(&mut reader, &mut piped).expect("failed to read pipe");
+        assert!(decompressing.join().expect("decompressing thread panicked").is_ok_and(|written| written == expected.len() as u64));
+        assert_eq!(expected, piped);
+    }
+
+    #[test]
+    fn test_compare_mode() {
+        let compressed = "testdata/alice_in_wonderland.txt.bz2";
+        let original = "testdata/alice-in-wonderland.txt";
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "--compare", compressed, original]);
+        assert!(matches!(cli.mode("bzip2rs"), cli::Mode::Compare));
+        assert!(do_main(vec!["bzip2rs", "-q", "--compare", compressed, original]).is_ok());
+
+        let mut changed = std::fs::read(original).expect("failed to read test file");
+        changed[1234] ^= 0x20;
+        std::fs::write("testdata/compare-changed.txt", changed).expect("failed to write test file");
+        let r = do_main(vec!["bzip2rs", "-q", "--compare", compressed, "testdata/compare-changed.txt"]);
+        assert!(r.is_err_and(|e| e.to_string().contains("differs from testdata/compare-changed.txt at byte 1234")));
+        assert!(do_main(vec!["bzip2rs", "-q", "--compare", compressed]).is_err());
+        std::fs::remove_file("testdata/compare-changed.txt").expect("failed to remove test file");
+    }
+
+    #[test]
+    fn test_compressed_block_count() {
+        let data = std::fs::read("testdata/alice-in-wonderland.txt").expect("failed to read test file").repeat(3);
+        let options = CompressionOptions::default().level(1);
+        let mut compressed = vec![];
+        let stats = bzip2::compress(data.as_slice(), &mut compressed, &options).expect("failed to compress");
+        assert_eq!(data.len() as u64, stats.read);
+        let blocks = bzip2::block_starts(&compressed).len();
+        assert!(blocks > 3);
+        #[cfg(not(feature = "sys"))]
+        {
+            assert_eq!(Some(blocks), stats.blocks);
+            assert_eq!(format!("{blocks} block(s) in {} stream(s)", stats.streams), stats.chunk_summary());
+        }
+        #[cfg(feature = "sys")]
+        assert_eq!("1 stream(s)", stats.chunk_summary());
+    }
+
+    #[test]
+    fn test_missing_output_dir() {
+        let dir = "testdata/missing-output-dir";
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::copy("testdata/alice-in-wonderland.txt", "testdata/missing-dir.txt").expect("failed to copy test file");
+        let output = format!("{dir}/nested/{{name}}.bz2");
+        let r = do_main(vec!["bzip2rs", "-q", "-k", "--output-template", &output, "testdata/missing-dir.txt"]);
+        assert!(r.is_err_and(|e| e.to_string().contains("testdata/missing-output-dir/nested: output directory does not exist")));
+        assert!(!Path::new(dir).exists());
+
+        assert!(do_main(vec!["bzip2rs", "-q", "-k", "--make-dirs", "--output-template", &output, "testdata/missing-dir.txt"]).is_ok());
+        let compressed = std::fs::read(format!("{dir}/nested/missing-dir.txt.bz2")).expect("failed to read test file");
+        assert_eq!(std::fs::read("testdata/alice-in-wonderland.txt").expect("failed to read test file"), decompress_to_vec(&compressed).expect("failed to decompress"));
+        std::fs::remove_dir_all(dir).expect("failed to remove test directory");
+        std::fs::remove_file("testdata/missing-dir.txt").expect("failed to remove test file");
+    }
+
+    #[test]
+    fn test_batch_total() {
+        assert_eq!("512 B", format_size(512));
+        assert_eq!("1.5 KiB", format_size(1536));
+        assert_eq!("120.0 MiB", format_size(120 * 1024 * 1024));
+        assert_eq!("Total: 120.0 MiB → 38.0 MiB (68.3% saved)", total_line(120 * 1024 * 1024, 38 * 1024 * 1024));
+
+        let files = ["testdata/batch-total1.txt", "testdata/batch-total2.txt"];
+        std::fs::copy("testdata/alice-in-wonderland.txt", files[0]).expect("failed to copy test file");
+        std::fs::write(files[1], b"hello, hello, hello, hello, world\n".repeat(100)).expect("failed to write test file");
+        let sizes = files.map(file_size);
+        let cli = cli::Bzip2Cli::parse_from(["bzip2rs", "-q", "-k", files[0], files[1]]);
+        let outcome = perform_compress(&cli, "bzip2rs", &mut vec![]).expect("failed to compress");
+        let compressed = files.map(|file| file_size(&format!("{file}.bz2")));
+        assert_eq!((sizes[0] + sizes[1], compressed[0] + compressed[1]), (outcome.read, outcome.written));
+        let saved = 100.0 * (1.0 - (compressed[0] + compressed[1]) as f64 / (sizes[0] + sizes[1]) as f64);
+        assert!(total_line(outcome.read, outcome.written).ends_with(&format!("({saved:.1}% saved)")));
+        for file in files {
+            std::fs::remove_file(file).expect("failed to remove test file");
+            std::fs::remove_file(format!("{file}.bz2")).expect("failed to remove test file");
+        }
+    }
+
+    #[test]
+    fn test_self_test() {
+        let mut out = vec![];
+        assert!(self_test(&mut out).is_ok());
+        let report = String::from_utf8(out).expect("output is not UTF-8");
+        assert_eq!(4, report.lines().filter(|line| line.starts_with("PASS\t")).count(), "{report}");
+        assert!(do_main(vec!["bzip2rs", "-q", "--self-test"]).is_ok());
+    }
+
+    #[test]
+    fn test_strict_suffix() {
+        let file = "testdata/strict-suffix.dat";
+        std::fs::copy("testdata/e.txt.bz2", file).expect("failed to copy test file");
+        let r = do_main(vec!["bzip2rs", "-q", "-d", "--strict-suffix", file]);
+        assert!(r.is_err_and(|e| matches!(e, Error::InvalidInput(_))));
+        assert!(!Path::new("testdata/strict-suffix.dat.out").exists());
+        assert!(Path::new(file).exists());
+        assert!(do_main(vec!["bzip2rs", "-q", "-d", "-k", file]).is_ok());
+        assert!(Path::new("testdata/strict-suffix.dat.out").exists());
+        for file in [file, "testdata/strict-suffix.dat.out"] {
+            std::fs::remove_file(file).expect("failed to remove test file");
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_output_linked_to_input() {
+        let original = std::fs::read("testdata/alice-in-wonderland.txt").expect("failed to read test file");
+        let input = "testdata/linked-input.txt";
+        std::fs::write(input, &original).expect("failed to write test file");
+        // a symlink in another directory, whose path differs from the input.
+        std::fs::create_dir_all("testdata/linked-output").expect("failed to create test directory");
+        std::os::unix::fs::symlink("../linked-input.txt", "testdata/linked-output/linked-input.txt.bz2").expect("failed to link test file");
+        let r = do_main(vec!["bzip2rs", "-q", "-f", "--output-dir", "testdata/linked-output", input]);
+        assert!(r.is_err_and(|e| matches!(e, Error::InvalidInput(_))));
+        assert_eq!(original, std::fs::read(input).expect("failed to read test file"));
+
+        // a hard link, which has no path to resolve at all.
+        std::fs::hard_link(input, "testdata/linked-input.txt.bz2").expect("failed to link test file");
+        assert!(is_same_identity(input, "testdata/linked-input.txt.bz2"));
+        let r = do_main(vec!["bzip2rs", "-q", "-f", input]);
+        assert!(r.is_err_and(|e| matches!(e, Error::InvalidInput(_))));
+        assert_eq!(original, std::fs::read(input).expect("failed to read test file"));
+        for file in [input, "testdata/linked-input.txt.bz2", "testdata/linked-output/linked-input.txt.bz2"] {
+            std::fs::remove_file(file).expect("failed to remove test file");
+        }
+        std::fs::remove_dir("testdata/linked-output").expect("failed to remove test directory");
+    }
+
+    #[test]
+    fn test_ratio_threshold_keeps_existing_output() {
+        let file = "testdata/random-threshold-append.bin";
+        let archive = "testdata/random-threshold-append.bin.bz2";
+        std::fs::write(file, pseudo_random_bytes(64 * 1024)).expect("failed to write test file");
+        assert!(do_main(vec!["bzip2rs", "-q", "-k", file]).is_ok());
+        let before = std::fs::read(archive).expect("failed to read test file");
+        assert!(do_main(vec!["bzip2rs", "-q", "-k", "--append", "--ratio-threshold", "0.95", file]).is_ok());
+        assert_eq!(before, std::fs::read(archive).expect("failed to read test file"));
+
+        std::fs::write(archive, b"existing").expect("failed to write test file");
+        assert!(do_main(vec!["bzip2rs", "-q", "-k", "-f", "--ratio-threshold", "0.95", file]).is_ok());
+        assert_eq!(b"existing".to_vec(), std::fs::read(archive).expect("failed to read test file"));
+        assert!(Path::new(file).exists());
+        for file in [file, archive] {
+            std::fs::remove_file(file).expect("failed to remove test file");
+        }
+    }
+
+    #[test]
+    fn test_checksum_trailer_single_stream() {
+        let r = do_main(vec!["bzip2rs", "-q", "-c", "--checksum-trailer", "testdata/alice-in-wonderland.txt", "testdata/e.txt.bz2"]);
+        assert!(matches!(r, Err(Error::InvalidInput(_))));
+        assert!(cli::Bzip2Cli::try_parse_from(["bzip2rs", "--append", "--checksum-trailer", "testdata/alice-in-wonderland.txt"]).is_err());
+    }
+}
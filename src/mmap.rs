@@ -0,0 +1,18 @@
+use std::fs::File;
+
+use memmap2::Mmap;
+
+/// Maps the whole of `file` read-only for `--mmap`, or returns `None` when it is not a
+/// non-empty regular file, which cannot be mapped as a whole.
+pub(crate) fn map(file: &File) -> std::io::Result<Option<Mmap>> {
+    let metadata = file.metadata()?;
+    if !metadata.is_file() || metadata.len() == 0 {
+        return Ok(None);
+    }
+    // SAFETY: the mapping is only read while the input is compressed. Another process may
+    // still modify or truncate the file meanwhile, which the mapping cannot prevent: changes
+    // show up in the compressed data, and reading pages past a truncated end raises SIGBUS.
+    // `--mmap` is therefore opt-in and documented as such, for inputs no one else writes.
+    let map = unsafe { Mmap::map(file)? };
+    Ok(Some(map))
+}
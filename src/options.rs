@@ -0,0 +1,90 @@
+use std::time::Duration;
+
+use crate::cli::Bzip2Cli;
+
+/// The settings of compressing data, for using bzip2rs as a library without a [`Bzip2Cli`].
+///
+/// ```
+/// use bzip2rs::CompressionOptions;
+///
+/// let options = CompressionOptions::default().level(9).small(false).workfactor(30);
+/// let compressed = bzip2rs::compress_to_vec(b"hello, hello, hello, world", options).unwrap();
+/// assert!(compressed.starts_with(b"BZh9"));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CompressionOptions {
+    pub(crate) level: usize,
+    pub(crate) small: bool,
+    pub(crate) work_factor: Option<u32>,
+    pub(crate) buffer_size: usize,
+    pub(crate) threads: usize,
+    pub(crate) limit_rate: u64,
+    pub(crate) flush_interval: Option<Duration>,
+}
+
+impl Default for CompressionOptions {
+    /// The settings of running `bzip2rs` without any options.
+    fn default() -> Self {
+        Self { level: 6, small: false, work_factor: None, buffer_size: 64 * 1024, threads: 1, limit_rate: 0, flush_interval: None }
+    }
+}
+
+impl CompressionOptions {
+    /// Sets the block size in units of 100k, clamped to 1 .. 9.
+    pub fn level(self, level: u8) -> Self {
+        Self { level: level.clamp(1, 9) as usize, ..self }
+    }
+
+    /// Sets whether memory use is reduced like `--small`. Compressing uses the same memory
+    /// either way; the pure Rust encoder warns that it ignores it.
+    pub fn small(self, small: bool) -> Self {
+        Self { small, ..self }
+    }
+
+    /// Sets the effort spent on repetitive data before falling back, at most 250. Only
+    /// libbzip2 supports it.
+    pub fn workfactor(self, work_factor: u32) -> Self {
+        Self { work_factor: Some(work_factor.min(250)), ..self }
+    }
+
+    /// Sets the size of the I/O buffers in bytes.
+    pub fn buffer_size(self, bytes: usize) -> Self {
+        Self { buffer_size: bytes.max(1), ..self }
+    }
+
+    /// Sets the number of worker threads of the pure Rust backend.
+    pub fn threads(self, threads: usize) -> Self {
+        Self { threads: threads.max(1), ..self }
+    }
+
+    /// Limits reading the data to compress to `bytes_per_sec`, or not at all with 0.
+    pub fn limit_rate(self, bytes_per_sec: u64) -> Self {
+        Self { limit_rate: bytes_per_sec, ..self }
+    }
+
+    /// Writes out the block being collected once it has waited for `interval`, even if it is
+    /// not full, so that a slow input is not held back. Only the pure Rust backend supports it.
+    pub fn flush_interval(self, interval: Duration) -> Self {
+        Self { flush_interval: Some(interval), ..self }
+    }
+}
+
+impl From<&Bzip2Cli> for CompressionOptions {
+    fn from(cli: &Bzip2Cli) -> Self {
+        Self {
+            level: cli.compress_level(),
+            small: cli.small,
+            work_factor: cli.work_factor,
+            buffer_size: cli.buffer_bytes(),
+            threads: cli.resolved_threads(),
+            limit_rate: cli.limit_rate,
+            flush_interval: cli.flush_interval.map(Duration::from_secs_f64),
+        }
+    }
+}
+
+impl From<&CompressionOptions> for CompressionOptions {
+    fn from(options: &CompressionOptions) -> Self {
+        *options
+    }
+}
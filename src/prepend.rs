@@ -0,0 +1,89 @@
+//! The common prefix of `--prepend`.
+//!
+//! Compressing with `--prepend PATH` compresses a header followed by the input, and
+//! decompressing with the same option strips the header again:
+//!
+//! | bytes | content                                    |
+//! |-------|--------------------------------------------|
+//! | 8     | n, the length of the prefix, little endian |
+//! | n     | the contents of the prefix file            |
+//!
+//! The header is part of the compressed data, so plain `bunzip2` restores it along with the
+//! input: files compressed with `--prepend` are only restored by `bzip2rs --prepend`.
+
+use std::io::{Cursor, Read, Write};
+
+/// The length of the marker recording the prefix length.
+const MARKER_LEN: usize = 8;
+
+/// The header compressed before each input with `--prepend`.
+pub(crate) struct Prefix {
+    header: Vec<u8>,
+}
+
+impl Prefix {
+    /// Reads the prefix file `path`.
+    pub fn load(path: &str) -> std::io::Result<Self> {
+        let prefix = std::fs::read(path)
+            .map_err(|e| std::io::Error::new(e.kind(), format!("{path}: {e}")))?;
+        let mut header = Vec::with_capacity(MARKER_LEN + prefix.len());
+        header.extend_from_slice(&(prefix.len() as u64).to_le_bytes());
+        header.extend_from_slice(&prefix);
+        Ok(Self { header })
+    }
+
+    /// The number of bytes added before each input.
+    pub fn len(&self) -> u64 {
+        self.header.len() as u64
+    }
+
+    /// Returns `reader` with the header in front of it.
+    pub fn prepend<R: Read>(&self, reader: R) -> impl Read + use<'_, R> {
+        Cursor::new(&self.header[..]).chain(reader)
+    }
+
+    /// Returns a writer passing what is written to `writer`, except for the header.
+    pub fn strip<W: Write>(&self, writer: W) -> Strip<'_, W> {
+        Strip { header: &self.header, seen: 0, writer }
+    }
+}
+
+/// A writer discarding the header of `--prepend` from the decompressed data.
+pub(crate) struct Strip<'a, W> {
+    header: &'a [u8],
+    seen: usize,
+    writer: W,
+}
+
+impl<W: Write> Strip<'_, W> {
+    /// Fails unless the whole header has been written, i.e. unless the data was compressed
+    /// with the same prefix.
+    pub fn finish(&self) -> std::io::Result<()> {
+        if self.seen < self.header.len() {
+            return Err(mismatch());
+        }
+        Ok(())
+    }
+}
+
+fn mismatch() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, "the data was not compressed with this --prepend file")
+}
+
+impl<W: Write> Write for Strip<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let skipped = (self.header.len() - self.seen).min(buf.len());
+        if buf[..skipped] != self.header[self.seen..self.seen + skipped] {
+            return Err(mismatch());
+        }
+        self.seen += skipped;
+        if skipped < buf.len() {
+            self.writer.write_all(&buf[skipped..])?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
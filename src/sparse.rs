@@ -0,0 +1,47 @@
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+
+/// The granularity in which runs of zero bytes are turned into holes.
+const HOLE_SIZE: u64 = 4096;
+
+/// A writer that skips over runs of zero bytes instead of writing them, like `cp --sparse=auto`,
+/// so that the output becomes a sparse file on filesystems supporting holes.
+pub(crate) struct SparseWriter<'a> {
+    file: &'a File,
+    position: u64,
+    len: u64,
+}
+
+impl<'a> SparseWriter<'a> {
+    pub fn new(file: &'a File) -> Self {
+        Self { file, position: 0, len: 0 }
+    }
+}
+
+impl Write for SparseWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut rest = buf;
+        while !rest.is_empty() {
+            let aligned = (HOLE_SIZE - self.position % HOLE_SIZE) as usize;
+            let (chunk, remaining) = rest.split_at(aligned.min(rest.len()));
+            if chunk.iter().all(|&b| b == 0) {
+                self.file.seek(SeekFrom::Current(chunk.len() as i64))?;
+            } else {
+                self.file.write_all(chunk)?;
+                self.len = self.position + chunk.len() as u64;
+            }
+            self.position += chunk.len() as u64;
+            rest = remaining;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if self.len < self.position {
+            // the output ends with a hole, extend the file to its full size.
+            self.file.set_len(self.position)?;
+            self.len = self.position;
+        }
+        self.file.flush()
+    }
+}
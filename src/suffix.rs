@@ -0,0 +1,61 @@
+/// The suffixes of compressed files and what they are replaced with on decompression,
+/// like bzip2. Longer suffixes come first so that `.bz2` is not taken for `.bz`.
+const SUFFIXES: &[(&str, &str)] = &[
+    (".tbz2", ".tar"),
+    (".bz2", ""),
+    (".tbz", ".tar"),
+    (".tz2", ".tar"),
+    (".bz", ""),
+];
+
+/// Splits `file` into its stem and compressed suffix, matching the suffix case-insensitively.
+/// The `custom` suffixes of `--suffix` are tried in order before the standard ones.
+/// Returns the stem, the suffix as written in `file`, and its replacement.
+fn split<'a>(file: &'a str, custom: &[&str]) -> Option<(&'a str, &'a str, &'static str)> {
+    custom.iter().filter(|suffix| !suffix.is_empty()).map(|suffix| (*suffix, ""))
+        .chain(SUFFIXES.iter().copied())
+        .find_map(|(suffix, replacement)| {
+            let at = file.len().checked_sub(suffix.len())?;
+            if file.is_char_boundary(at) && file[at..].eq_ignore_ascii_case(suffix) {
+                Some((&file[..at], &file[at..], replacement))
+            } else {
+                None
+            }
+        })
+}
+
+/// Returns the compressed suffix of `file` as written, if it has one.
+pub(crate) fn compressed_suffix<'a>(file: &'a str, custom: &[&str]) -> Option<&'a str> {
+    split(file, custom).map(|(_, suffix, _)| suffix)
+}
+
+/// Guesses the original name of the compressed `file`. The replacement suffix is upper-cased
+/// when the compressed suffix was, so `BACKUP.TBZ2` becomes `BACKUP.TAR`.
+/// Only the last suffix is stripped, so that `data.bz2.bz2` becomes `data.bz2`, peeling one
+/// layer of compression per run.
+pub(crate) fn original_name(file: &str, custom: &[&str]) -> Option<String> {
+    split(file, custom).map(|(stem, suffix, replacement)| {
+        if suffix.chars().any(|c| c.is_ascii_lowercase()) {
+            format!("{stem}{replacement}")
+        } else {
+            format!("{stem}{}", replacement.to_ascii_uppercase())
+        }
+    })
+}
+
+/// The magic numbers of compressed formats, which `--skip-compressed` looks for at the start
+/// of the input regardless of its name.
+const MAGICS: &[(&[u8], &str)] = &[
+    (b"BZh", "bzip2"),
+    (b"\x1f\x8b", "gzip"),
+    (b"\x28\xb5\x2f\xfd", "zstd"),
+    (b"\xfd7zXZ\x00", "xz"),
+];
+
+/// Returns the name of the compressed format `file` starts with, if it is one of the known.
+pub(crate) fn compressed_format(file: &str) -> Option<&'static str> {
+    use std::io::Read;
+    let mut head = Vec::with_capacity(6);
+    std::fs::File::open(file).ok()?.take(6).read_to_end(&mut head).ok()?;
+    MAGICS.iter().find(|(magic, _)| head.starts_with(magic)).map(|(_, name)| *name)
+}
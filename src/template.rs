@@ -0,0 +1,78 @@
+//! The output names of `--output-template`.
+//!
+//! A template is the path of an output with placeholders for parts of its input file:
+//!
+//! | placeholder | for `docs/notes.txt` |
+//! |-------------|----------------------|
+//! | `{name}`    | `notes.txt`          |
+//! | `{stem}`    | `notes`              |
+//! | `{ext}`     | `txt`                |
+//! | `{dir}`     | `docs`               |
+//!
+//! `{{` and `}}` stand for literal braces.
+
+use std::path::Path;
+
+/// A piece of a template.
+enum Part<'a> {
+    Literal(&'a str),
+    Name,
+    Stem,
+    Ext,
+    Dir,
+}
+
+/// Splits `template` into its pieces, failing on unknown placeholders and unmatched braces.
+fn parse(template: &str) -> Result<Vec<Part<'_>>, String> {
+    let mut parts = vec![];
+    let mut rest = template;
+    while let Some(at) = rest.find(['{', '}']) {
+        parts.push(Part::Literal(&rest[..at]));
+        rest = &rest[at..];
+        if let Some(after) = rest.strip_prefix("{{") {
+            parts.push(Part::Literal("{"));
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("}}") {
+            parts.push(Part::Literal("}"));
+            rest = after;
+        } else if rest.starts_with('}') {
+            return Err(format!("unmatched '}}' in {template}"));
+        } else {
+            let end = rest.find('}').ok_or_else(|| format!("unmatched '{{' in {template}"))?;
+            parts.push(match &rest[1..end] {
+                "name" => Part::Name,
+                "stem" => Part::Stem,
+                "ext" => Part::Ext,
+                "dir" => Part::Dir,
+                other => return Err(format!("unknown placeholder {{{other}}}, expected {{name}}, {{stem}}, {{ext}} or {{dir}}")),
+            });
+            rest = &rest[end + 1..];
+        }
+    }
+    parts.push(Part::Literal(rest));
+    Ok(parts)
+}
+
+/// Checks `template` when the command line is parsed, so that a bad one fails before any file
+/// is processed.
+pub(crate) fn validate(template: &str) -> Result<String, String> {
+    parse(template).map(|_| template.to_string())
+}
+
+/// Returns the output path `template` gives for the input `file`. The directory of a file
+/// without one is `.`, and the extension of a file without one is empty.
+pub(crate) fn render(template: &str, file: &str) -> String {
+    let path = Path::new(file);
+    let lossy = |part: Option<&std::ffi::OsStr>| part.map(|part| part.to_string_lossy().into_owned()).unwrap_or_default();
+    let dir = match path.parent().map(Path::as_os_str) {
+        Some(dir) if !dir.is_empty() => dir.to_string_lossy().into_owned(),
+        _ => ".".to_string(),
+    };
+    parse(template).unwrap_or_default().into_iter().map(|part| match part {
+        Part::Literal(literal) => literal.to_string(),
+        Part::Name => lossy(path.file_name()),
+        Part::Stem => lossy(path.file_stem()),
+        Part::Ext => lossy(path.extension()),
+        Part::Dir => dir.clone(),
+    }).collect()
+}
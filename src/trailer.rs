@@ -0,0 +1,150 @@
+//! The trailers written by `--checksum-trailer` and `--name`.
+//!
+//! The trailers follow the last bzip2 stream of a file, the checksum trailer first. The
+//! checksum trailer records the CRC32 of the original data:
+//!
+//! | bytes | content                                          |
+//! |-------|--------------------------------------------------|
+//! | 5     | the magic `BZrsC`                                |
+//! | 4     | the CRC32 of the original data, little endian    |
+//! | 5     | the magic `BZrsC` again                          |
+//!
+//! The name trailer records the name of the original file:
+//!
+//! | bytes | content                                          |
+//! |-------|--------------------------------------------------|
+//! | 5     | the magic `BZrsN`                                |
+//! | n     | the file name, UTF-8 encoded                     |
+//! | 2     | n, little endian                                 |
+//! | 5     | the magic `BZrsN` again                          |
+//!
+//! The length and the second magic come last, so that the trailers can be found by reading
+//! the end of the file. Since the trailers do not start with a stream signature, bzip2
+//! takes them for trailing garbage, warns and ignores them.
+
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// The magic number enclosing the name trailer.
+const MAGIC: &[u8; 5] = b"BZrsN";
+/// The length of the fixed part at the end of the name trailer: the name length and the magic.
+const FOOTER_LEN: usize = 2 + MAGIC.len();
+/// The magic number enclosing the checksum trailer.
+const CHECKSUM_MAGIC: &[u8; 5] = b"BZrsC";
+/// The length of the checksum trailer.
+const CHECKSUM_LEN: usize = 2 * CHECKSUM_MAGIC.len() + 4;
+/// The longest the trailers can be together.
+const MAX_TRAILERS_LEN: usize = MAGIC.len() + u16::MAX as usize + FOOTER_LEN + CHECKSUM_LEN;
+
+/// Writes the trailer recording `name` to `writer`. Names longer than 65535 bytes are
+/// not recorded.
+pub(crate) fn write_name(mut writer: impl Write, name: &str) -> std::io::Result<()> {
+    let Ok(len) = u16::try_from(name.len()) else {
+        log::warn!("bzip2: {name}: name too long to be stored, skipped");
+        return Ok(());
+    };
+    let mut trailer = Vec::with_capacity(MAGIC.len() + name.len() + FOOTER_LEN);
+    trailer.extend_from_slice(MAGIC);
+    trailer.extend_from_slice(name.as_bytes());
+    trailer.extend_from_slice(&len.to_le_bytes());
+    trailer.extend_from_slice(MAGIC);
+    writer.write_all(&trailer)?;
+    writer.flush()
+}
+
+/// Writes the trailer recording the CRC32 `crc` of the original data to `writer`.
+pub(crate) fn write_checksum(mut writer: impl Write, crc: u32) -> std::io::Result<()> {
+    let mut trailer = Vec::with_capacity(CHECKSUM_LEN);
+    trailer.extend_from_slice(CHECKSUM_MAGIC);
+    trailer.extend_from_slice(&crc.to_le_bytes());
+    trailer.extend_from_slice(CHECKSUM_MAGIC);
+    writer.write_all(&trailer)?;
+    writer.flush()
+}
+
+/// Returns the length of the trailers `bytes` ends with, if it ends with any complete one.
+pub(crate) fn trailer_len(bytes: &[u8]) -> Option<usize> {
+    let name = name_len(bytes).unwrap_or(0);
+    let checksum = checksum(&bytes[..bytes.len() - name]).map_or(0, |_| CHECKSUM_LEN);
+    (name + checksum > 0).then_some(name + checksum)
+}
+
+/// Returns the CRC32 recorded by the checksum trailer `bytes` ends with, if it ends with one.
+fn checksum(bytes: &[u8]) -> Option<u32> {
+    let trailer = bytes.len().checked_sub(CHECKSUM_LEN).map(|at| &bytes[at..])?;
+    let magic = CHECKSUM_MAGIC.len();
+    (&trailer[..magic] == CHECKSUM_MAGIC && &trailer[magic + 4..] == CHECKSUM_MAGIC)
+        .then(|| u32::from_le_bytes([trailer[magic], trailer[magic + 1], trailer[magic + 2], trailer[magic + 3]]))
+}
+
+/// Returns the length of the name trailer `bytes` ends with, if it ends with a complete one.
+fn name_len(bytes: &[u8]) -> Option<usize> {
+    let footer = bytes.len().checked_sub(FOOTER_LEN).map(|at| &bytes[at..])?;
+    if &footer[2..] != MAGIC {
+        return None;
+    }
+    let len = MAGIC.len() + u16::from_le_bytes([footer[0], footer[1]]) as usize + FOOTER_LEN;
+    let start = bytes.len().checked_sub(len)?;
+    bytes[start..].starts_with(MAGIC).then_some(len)
+}
+
+/// Reads the name recorded in the trailer at the end of `reader`, if there is one.
+pub(crate) fn read_name(mut reader: impl Read + Seek) -> std::io::Result<Option<String>> {
+    let size = reader.seek(SeekFrom::End(0))?;
+    if size < (MAGIC.len() + FOOTER_LEN) as u64 {
+        return Ok(None);
+    }
+    let mut footer = [0u8; FOOTER_LEN];
+    reader.seek(SeekFrom::End(-(FOOTER_LEN as i64)))?;
+    reader.read_exact(&mut footer)?;
+    if &footer[2..] != MAGIC {
+        return Ok(None);
+    }
+    let len = u16::from_le_bytes([footer[0], footer[1]]) as usize;
+    let trailer_len = (MAGIC.len() + len + FOOTER_LEN) as u64;
+    if size < trailer_len {
+        return Ok(None);
+    }
+    let mut trailer = vec![0u8; MAGIC.len() + len];
+    reader.seek(SeekFrom::End(-(trailer_len as i64)))?;
+    reader.read_exact(&mut trailer)?;
+    if &trailer[..MAGIC.len()] != MAGIC {
+        return Ok(None);
+    }
+    Ok(String::from_utf8(trailer.split_off(MAGIC.len())).ok())
+}
+
+/// Reads the CRC32 recorded in the checksum trailer at the end of `reader`, if there is one.
+pub(crate) fn read_checksum(mut reader: impl Read + Seek) -> std::io::Result<Option<u32>> {
+    let size = reader.seek(SeekFrom::End(0))?;
+    let len = size.min(MAX_TRAILERS_LEN as u64);
+    let mut tail = vec![0u8; len as usize];
+    reader.seek(SeekFrom::End(-(len as i64)))?;
+    reader.read_exact(&mut tail)?;
+    let name = name_len(&tail).unwrap_or(0);
+    Ok(checksum(&tail[..tail.len() - name]))
+}
+
+/// A reader computing the CRC32 of everything read through it, for `--checksum-trailer`.
+pub(crate) struct CrcReader<R> {
+    inner: R,
+    hasher: crc32fast::Hasher,
+}
+
+impl<R> CrcReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner, hasher: crc32fast::Hasher::new() }
+    }
+
+    /// Returns the CRC32 of the bytes read so far.
+    pub fn crc(&self) -> u32 {
+        self.hasher.clone().finalize()
+    }
+}
+
+impl<R: Read> Read for CrcReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.hasher.update(&buf[..len]);
+        Ok(len)
+    }
+}
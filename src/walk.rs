@@ -0,0 +1,132 @@
+use std::path::Path;
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+
+use crate::cli::Bzip2Cli;
+use crate::{Error, Result};
+
+/// The `--include` and `--exclude` patterns, matched against the names of the walked entries.
+struct Filter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl Filter {
+    fn new(cli: &Bzip2Cli) -> Result<Self> {
+        let include = if cli.include.is_empty() { None } else { Some(glob_set(&cli.include)?) };
+        Ok(Self { include, exclude: glob_set(&cli.exclude)? })
+    }
+
+    fn includes_file(&self, name: &str) -> bool {
+        self.include.as_ref().is_none_or(|include| include.is_match(name)) && !self.exclude.is_match(name)
+    }
+
+    fn includes_dir(&self, name: &str) -> bool {
+        !self.exclude.is_match(name)
+    }
+}
+
+fn glob_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern)
+            .map_err(|e| Error::InvalidInput(format!("bzip2: invalid pattern {pattern}: {e}")))?;
+        builder.add(glob);
+    }
+    builder.build()
+        .map_err(|e| Error::InvalidInput(format!("bzip2: invalid patterns: {e}")))
+}
+
+/// Returns the files to process: with `--recursive`, directories among the input files are
+/// replaced by the files found in them. Directories that cannot be read are reported to `errs`
+/// and skipped.
+pub(crate) fn expand_inputs(cli: &Bzip2Cli, errs: &mut Vec<Error>) -> Result<Vec<String>> {
+    let inputs = if cli.glob || cfg!(windows) { expand_globs(&cli.input_files)? } else { cli.input_files.clone() };
+    if !cli.recursive {
+        return Ok(inputs);
+    }
+    let filter = Filter::new(cli)?;
+    let mut files = vec![];
+    for input in inputs {
+        let path = Path::new(&input);
+        if path.is_dir() {
+            walk(path, 1, cli.max_depth, &filter, &mut files, errs);
+        } else {
+            files.push(input);
+        }
+    }
+    Ok(files)
+}
+
+/// Replaces the wildcard patterns among `inputs` by the files they match, for shells that do
+/// not expand them, such as on Windows. Inputs naming an existing file are taken literally.
+/// Wildcards are only expanded in the last component of a pattern.
+fn expand_globs(inputs: &[String]) -> Result<Vec<String>> {
+    let mut files = vec![];
+    for input in inputs {
+        if !input.contains(['*', '?', '[']) || Path::new(input).exists() {
+            files.push(input.clone());
+            continue;
+        }
+        let path = Path::new(input);
+        let pattern = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        let matcher = Glob::new(&pattern)
+            .map_err(|e| Error::InvalidInput(format!("bzip2: invalid pattern {input}: {e}")))?
+            .compile_matcher();
+        let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+        let mut matches = std::fs::read_dir(dir.unwrap_or(Path::new(".")))
+            .map(|entries| entries.filter_map(|entry| entry.ok())
+                .filter(|entry| matcher.is_match(entry.file_name()))
+                .map(|entry| match dir {
+                    Some(dir) => dir.join(entry.file_name()).to_string_lossy().into_owned(),
+                    None => entry.file_name().to_string_lossy().into_owned(),
+                })
+                .collect::<Vec<_>>())
+            .unwrap_or_default();
+        if matches.is_empty() {
+            return Err(Error::InvalidInput(format!("bzip2: {input}: no files match the pattern")));
+        }
+        matches.sort();
+        files.append(&mut matches);
+    }
+    Ok(files)
+}
+
+/// Collects the files in `dir`, which are `depth` levels below an input directory, and
+/// descends into its subdirectories while they are within `max_depth`.
+/// Symbolic links to directories are not followed, so that cycles cannot occur.
+fn walk(dir: &Path, depth: usize, max_depth: Option<usize>, filter: &Filter, files: &mut Vec<String>, errs: &mut Vec<Error>) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            errs.push(Error::Io(std::io::Error::new(e.kind(), format!("{}: {e}", dir.display()))));
+            return;
+        },
+    };
+    let mut entries = entries.filter_map(|entry| match entry {
+        Ok(entry) => Some(entry),
+        Err(e) => {
+            errs.push(Error::Io(e));
+            None
+        },
+    }).collect::<Vec<_>>();
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().into_owned();
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => {
+                if max_depth.is_none_or(|max| depth < max) && filter.includes_dir(&name) {
+                    walk(&path, depth + 1, max_depth, filter, files, errs);
+                }
+            },
+            Ok(_) if path.is_file() => {
+                if filter.includes_file(&name) {
+                    files.push(path.to_string_lossy().into_owned());
+                }
+            },
+            Ok(_) => log::info!("{}: not a regular file, skipped", path.display()),
+            Err(e) => errs.push(Error::Io(e)),
+        }
+    }
+}